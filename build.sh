@@ -34,4 +34,4 @@ ffmpeg -i "$INPUT_VIDEO" \
     -vf "fps=${OUTPUT_FRAMERATE},scale=${OUTPUT_WIDTH}:${OUTPUT_HEIGHT}" \
     frames/%05d.png
 
-cargo r -- "$SAVE_FILE/data.logicworld"
+cargo r -- inject --in-place --source-fps "$OUTPUT_FRAMERATE" "$SAVE_FILE/data.logicworld"
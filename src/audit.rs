@@ -0,0 +1,189 @@
+//! Checks of the wiring of a generated circuit for mistakes the game accepts silently, and which
+//! would otherwise only be found by looking through the display in the world.
+//!
+//! Pins joined by wires form a net. Every net should be driven by at least one delayer output,
+//! except for the net of the first timing step, which is left for the player to connect.
+
+use crate::circuit::{Circuit, Kind, NodeId, Pin, Slot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// A wire connects this output directly to another output, which the game does not allow.
+    JoinedOutputs { other: NodeId },
+    /// No output drives the net of this input, so it never turns on.
+    FloatingInput,
+    /// This peg is not wired to anything.
+    UnconnectedPeg,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Finding {
+    pub problem: Problem,
+    /// Component the problem was found at; for a net, its first input.
+    pub node: NodeId,
+}
+
+/// Returns the wiring problems of `circuit`, in component order. Expects a
+/// [validated](Circuit::validate) circuit.
+pub fn audit(circuit: &Circuit) -> Vec<Finding> {
+    let count = circuit.components.len();
+    // Inputs are numbered 0..count, outputs count..2 * count.
+    let index = |pin: Pin| match pin {
+        Pin::Input(node) => node.0,
+        Pin::Output(node) => count + node.0,
+    };
+
+    let mut nets = Nets::new(2 * count);
+    let mut wired = vec![false; 2 * count];
+    let mut findings = Vec::new();
+    for wire in &circuit.wires {
+        if let (Pin::Output(node), Pin::Output(other)) = (wire.from, wire.to) {
+            findings.push(Finding {
+                problem: Problem::JoinedOutputs { other },
+                node,
+            });
+        }
+        let (from, to) = (index(wire.from), index(wire.to));
+        nets.join(from, to);
+        wired[from] = true;
+        wired[to] = true;
+    }
+
+    let mut driven = vec![false; 2 * count];
+    let mut started = vec![false; 2 * count];
+    for (id, component) in circuit.components.iter().enumerate() {
+        if matches!(component.kind, Kind::Delayer { .. }) && wired[count + id] {
+            let net = nets.find(count + id);
+            driven[net] = true;
+        }
+        if matches!(component.slot, Slot::Timing { z: 0, .. }) {
+            let net = nets.find(id);
+            started[net] = true;
+        }
+    }
+
+    let mut reported = vec![false; 2 * count];
+    for (id, component) in circuit.components.iter().enumerate() {
+        let net = nets.find(id);
+        let problem = if !wired[id] {
+            match component.kind {
+                Kind::Peg => Some(Problem::UnconnectedPeg),
+                // A display socket is only wired if its column ever lights up.
+                Kind::Socket => None,
+                // Nor is a chunking delayer past the last change of its column.
+                Kind::Delayer { .. } if matches!(component.slot, Slot::Junction { .. }) => None,
+                Kind::Delayer { .. } => (!started[net]).then_some(Problem::FloatingInput),
+            }
+        } else if !driven[net] && !started[net] {
+            Some(Problem::FloatingInput)
+        } else {
+            None
+        };
+        if let Some(problem) = problem.filter(|_| !reported[net]) {
+            reported[net] = true;
+            findings.push(Finding {
+                problem,
+                node: NodeId(id),
+            });
+        }
+    }
+    findings.sort_by_key(|finding| finding.node.0);
+    findings
+}
+
+/// Disjoint sets of pins, joined by wires.
+struct Nets {
+    parents: Vec<usize>,
+}
+
+impl Nets {
+    fn new(pins: usize) -> Self {
+        Self {
+            parents: (0..pins).collect(),
+        }
+    }
+
+    fn find(&mut self, mut pin: usize) -> usize {
+        while self.parents[pin] != pin {
+            self.parents[pin] = self.parents[self.parents[pin]];
+            pin = self.parents[pin];
+        }
+        pin
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a] = b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One column of a display showing a single frame: a timing chain of two delayers, and a
+    /// pixel delayer driving the socket from the second step.
+    fn column() -> Circuit {
+        let mut circuit = Circuit::new();
+        let start = circuit.add(Kind::Delayer { delay: 10 }, Slot::Timing { row: 0, z: 0 });
+        let step = circuit.add(Kind::Delayer { delay: 10 }, Slot::Timing { row: 0, z: 1 });
+        let socket = circuit.add(Kind::Socket, Slot::Socket { row: 0, column: 0 });
+        let pixel = circuit.add(
+            Kind::Delayer { delay: 1 },
+            Slot::Pixel {
+                row: 0,
+                column: 0,
+                z: 1,
+            },
+        );
+        circuit.wire(Pin::Output(start), Pin::Input(step));
+        circuit.wire(Pin::Input(step), Pin::Input(pixel));
+        circuit.wire(Pin::Output(pixel), Pin::Input(socket));
+        circuit
+    }
+
+    fn problems(circuit: &Circuit) -> Vec<(Problem, NodeId)> {
+        audit(circuit)
+            .into_iter()
+            .map(|finding| (finding.problem, finding.node))
+            .collect()
+    }
+
+    #[test]
+    fn generated_wiring_passes() {
+        assert!(problems(&column()).is_empty());
+    }
+
+    #[test]
+    fn finds_each_kind_of_problem() {
+        let mut circuit = column();
+        let (start, pixel) = (NodeId(0), NodeId(3));
+        circuit.wire(Pin::Output(pixel), Pin::Output(start));
+        let peg = circuit.add(Kind::Peg, Slot::RowBus { row: 0, z: 3 });
+        let junction = circuit.add(
+            Kind::Peg,
+            Slot::Junction {
+                row: 0,
+                column: 1,
+                z: 3,
+            },
+        );
+        let floating = circuit.add(
+            Kind::Delayer { delay: 1 },
+            Slot::Pixel {
+                row: 0,
+                column: 1,
+                z: 3,
+            },
+        );
+        circuit.wire(Pin::Input(junction), Pin::Input(floating));
+        assert_eq!(
+            problems(&circuit),
+            [
+                (Problem::JoinedOutputs { other: start }, pixel),
+                (Problem::UnconnectedPeg, peg),
+                (Problem::FloatingInput, junction),
+            ]
+        );
+    }
+}
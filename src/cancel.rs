@@ -0,0 +1,29 @@
+//! Cooperative cancellation of a run.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A flag shared between a run and whoever may want to stop it. Runs check it between frames,
+/// including frames decoded ahead or read up front, so cancelling takes effect at the next frame
+/// boundary. [`encode`](crate::encode::encode) then fails with
+/// [`Failure::Cancelled`](crate::failure::Failure::Cancelled).
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,184 @@
+//! Layout-independent description of the generated circuit.
+//!
+//! The encoder builds a [`Circuit`] out of components identified by their logical [`Slot`] in the
+//! display driver. Positions are only assigned afterwards, by a [`Layout`](crate::layout::Layout).
+
+use std::collections::HashMap;
+
+use anyhow::bail;
+
+/// Index of a component in a [`Circuit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Delayer { delay: u32 },
+    Peg,
+    Socket,
+}
+
+/// Where a component sits in the logical structure of the display driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Slot {
+    /// The `z`th delayer of a row's timing chain.
+    Timing { row: usize, z: usize },
+    /// The display socket at the end of a column's signal chain.
+    Socket { row: usize, column: usize },
+    /// A junction in a column's signal chain at timing step `z`: a peg or a chunking delayer.
+    Junction { row: usize, column: usize, z: usize },
+    /// The delayer forwarding timing step `z` into a column.
+    Pixel { row: usize, column: usize, z: usize },
+    /// The peg feeding a row's pixel chain from timing step `z`, used with tidy wiring.
+    RowBus { row: usize, z: usize },
+    /// A peg exposing a row's frame-advance signal at the chunk boundary at timing step `z`.
+    ChunkTap { row: usize, z: usize },
+}
+
+impl Slot {
+    pub fn row(&self) -> usize {
+        match *self {
+            Slot::Timing { row, .. }
+            | Slot::Socket { row, .. }
+            | Slot::Junction { row, .. }
+            | Slot::Pixel { row, .. }
+            | Slot::RowBus { row, .. }
+            | Slot::ChunkTap { row, .. } => row,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Component {
+    pub kind: Kind,
+    pub slot: Slot,
+}
+
+/// One end of a wire.
+///
+/// Every component kind has exactly one input, and delayers have exactly one output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pin {
+    Input(NodeId),
+    Output(NodeId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Wire {
+    pub from: Pin,
+    pub to: Pin,
+}
+
+#[derive(Default)]
+pub struct Circuit {
+    pub components: Vec<Component>,
+    pub wires: Vec<Wire>,
+    slots: HashMap<Slot, NodeId>,
+    /// For a [fragment](Self::fragment), the number of components of the circuit it continues.
+    /// Node IDs below it refer to those components.
+    base: usize,
+}
+
+/// How the node IDs of a fragment map onto the circuit it was [appended](Circuit::append) to.
+#[derive(Clone, Copy)]
+pub struct Renumbering {
+    base: usize,
+    offset: usize,
+}
+
+impl Renumbering {
+    pub fn node(self, id: NodeId) -> NodeId {
+        if id.0 < self.base {
+            id
+        } else {
+            NodeId(id.0 - self.base + self.offset)
+        }
+    }
+
+    pub fn pin(self, pin: Pin) -> Pin {
+        match pin {
+            Pin::Input(id) => Pin::Input(self.node(id)),
+            Pin::Output(id) => Pin::Output(self.node(id)),
+        }
+    }
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts an empty circuit that continues this one, so that its wires may also refer to the
+    /// components added here so far. Several fragments may be started from the same circuit, and
+    /// built independently before being appended.
+    pub fn fragment(&self) -> Self {
+        Self {
+            base: self.components.len(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the components and wires of a fragment of this circuit, and returns how its node IDs
+    /// were renumbered. Components added since the fragment was started are left alone, and the
+    /// fragment's components are numbered after them.
+    pub fn append(&mut self, fragment: Circuit) -> Renumbering {
+        let renumbering = Renumbering {
+            base: fragment.base,
+            offset: self.components.len(),
+        };
+        self.components.extend(fragment.components);
+        self.wires
+            .extend(fragment.wires.into_iter().map(|wire| Wire {
+                from: renumbering.pin(wire.from),
+                to: renumbering.pin(wire.to),
+            }));
+        self.slots.extend(
+            fragment
+                .slots
+                .into_iter()
+                .map(|(slot, id)| (slot, renumbering.node(id))),
+        );
+        renumbering
+    }
+
+    /// Adds a component, replacing any component previously recorded for the same slot.
+    pub fn add(&mut self, kind: Kind, slot: Slot) -> NodeId {
+        let id = NodeId(self.base + self.components.len());
+        self.components.push(Component { kind, slot });
+        self.slots.insert(slot, id);
+        id
+    }
+
+    /// Returns the component occupying a slot, if any.
+    pub fn at(&self, slot: Slot) -> Option<NodeId> {
+        self.slots.get(&slot).copied()
+    }
+
+    pub fn wire(&mut self, from: Pin, to: Pin) {
+        self.wires.push(Wire { from, to });
+    }
+
+    /// Checks that every wire connects pins that exist, so that emitting the circuit cannot
+    /// fail partway through.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for wire in &self.wires {
+            for pin in [wire.from, wire.to] {
+                let (node, is_output) = match pin {
+                    Pin::Input(node) => (node, false),
+                    Pin::Output(node) => (node, true),
+                };
+                let Some(component) = self.components.get(node.0) else {
+                    bail!("wire {:?} refers to a missing component", wire);
+                };
+                if is_output && !matches!(component.kind, Kind::Delayer { .. }) {
+                    bail!(
+                        "wire {:?} refers to the output of a {:?}",
+                        wire,
+                        component.kind
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
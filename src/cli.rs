@@ -0,0 +1,495 @@
+//! Command-line interface.
+
+use std::{path::PathBuf, thread};
+
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use clap_complete::Shell;
+use glob::Pattern;
+use logicworld_badapple::{
+    encode::{self, DEFAULT_MAX_SIZE_MIB, DEFAULT_PREFETCH, DEFAULT_TICK_RATE},
+    layout::Facing,
+    preprocess::{
+        AlphaBackground, Aspect, ContrastStretch, Dither, Filter, Mode, Origin, PanZoom, Rect,
+        Rotation, Size, DEFAULT_THRESHOLD,
+    },
+    timing::CHUNK_INTERVAL,
+};
+
+/// Width of the display generated for --ticker, in pixels.
+const DEFAULT_TICKER_WIDTH: usize = 32;
+
+/// Plays back a video on a display driver generated into a Logic World save.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Encode the video and add it to a save
+    Inject(InjectArgs),
+    /// Encode the video and report its size, without writing anything
+    Estimate(Options),
+    /// Report pixel toggles and lit pixels over a range of thresholds, to help pick one
+    Sweep(SweepArgs),
+    /// Encode the video and describe the circuit it would generate, without writing to any save
+    Plan(PlanArgs),
+    /// Remove frames downloaded into the local cache
+    Clean,
+    /// Print a completion script for this shell to standard output
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page to standard output, or write one page per command
+    Man(ManArgs),
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("destination").args(["output", "in_place"])))]
+pub struct InjectArgs {
+    /// Save file to add the video to, or - to read it from standard input and write the result
+    /// to standard output
+    #[arg(required_unless_present = "schematic")]
+    pub path: Option<PathBuf>,
+
+    /// Write the modified save to this file, or - for standard output, leaving the original
+    /// untouched
+    #[arg(short, long, requires = "path")]
+    pub output: Option<PathBuf>,
+
+    /// Overwrite the original save
+    #[arg(long, requires = "path")]
+    pub in_place: bool,
+
+    /// Do not copy the original save to `<name>.bak-<timestamp>` before overwriting it
+    #[arg(long, requires = "in_place")]
+    pub no_backup: bool,
+
+    /// Also write the circuit as a JSON schematic to this file
+    #[arg(long)]
+    pub schematic: Option<PathBuf>,
+
+    /// Record the displayed frames, effective parameters and tool version to this lockfile
+    #[arg(long)]
+    pub lockfile: Option<PathBuf>,
+
+    /// Refuse to run unless the frames, parameters and tool version match the lockfile, which
+    /// is then left unchanged
+    #[arg(long, requires = "lockfile")]
+    pub locked: bool,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Args)]
+pub struct SweepArgs {
+    /// Lowest threshold to try
+    #[arg(long, default_value_t = 16)]
+    pub min: u8,
+
+    /// Highest threshold to try
+    #[arg(long, default_value_t = 240)]
+    pub max: u8,
+
+    /// Step between thresholds
+    #[arg(long, default_value_t = 16, value_parser = at_least_one)]
+    pub step: usize,
+
+    /// Number of frames to sample, from the start of the video
+    #[arg(long, default_value_t = 200, value_parser = at_least_one)]
+    pub sample: usize,
+
+    /// Directory to write a preview of the middle sampled frame to, for each threshold
+    #[arg(long)]
+    pub previews: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    /// Also write the plan as JSON to this file
+    #[arg(long)]
+    pub json: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Args)]
+pub struct ManArgs {
+    /// Write a page for the tool and one for each of its commands into this directory
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+}
+
+/// Options shared by every command that encodes the video.
+#[derive(Args)]
+pub struct Options {
+    /// Directory, or ZIP or TAR archive, to read frames from, in file name order. An archive
+    /// may be given as an HTTP(S) URL to download it from; a directory cannot
+    #[arg(long, default_value = "frames")]
+    pub frames: PathBuf,
+
+    /// Only read frames whose file name matches this glob, such as "*.png"
+    #[arg(long, conflicts_with = "frame_list")]
+    pub pattern: Option<Pattern>,
+
+    /// File listing frame paths or URLs in playback order, one per line
+    #[arg(long, conflicts_with = "frame_archive")]
+    pub frame_list: Option<PathBuf>,
+
+    /// ZIP or TAR archive, or HTTP(S) URL of one, to read frames from, in entry name order
+    #[arg(long)]
+    pub frame_archive: Option<PathBuf>,
+
+    /// Video file, or HTTP(S) URL of one, to decode frames from with ffmpeg, instead of a
+    /// directory of frames
+    #[arg(long, conflicts_with_all = ["frame_list", "frame_archive", "pattern"])]
+    pub video: Option<PathBuf>,
+
+    /// yuv4mpeg2 stream to read frames from, or - to read it from standard input
+    #[arg(long, conflicts_with_all = ["frame_list", "frame_archive", "video", "animation", "pattern"])]
+    pub y4m: Option<PathBuf>,
+
+    /// Animated GIF or PNG to read frames from, each shown for its own delay unless
+    /// --half-period is given
+    #[arg(long, alias = "gif", conflicts_with_all = ["frame_list", "frame_archive", "video", "pattern"])]
+    pub animation: Option<PathBuf>,
+
+    /// Play this text scrolling across the display instead of reading frames
+    #[arg(long, conflicts_with_all = ["frame_list", "frame_archive", "video", "animation", "y4m"])]
+    pub ticker: Option<String>,
+
+    /// Width of the ticker display, in pixels
+    #[arg(long, requires = "ticker", default_value_t = DEFAULT_TICKER_WIDTH, value_parser = at_least_one)]
+    pub ticker_width: usize,
+
+    /// Number of pixels the ticker text moves each frame
+    #[arg(long, requires = "ticker", default_value_t = 1, value_parser = at_least_one)]
+    pub ticker_speed: usize,
+
+    /// Skip this many source frames at the start
+    #[arg(long, default_value_t = 0)]
+    pub start_frame: usize,
+
+    /// Stop before this source frame
+    #[arg(long)]
+    pub end_frame: Option<usize>,
+
+    /// Only use every nth of the remaining source frames
+    #[arg(long, default_value_t = 1, value_parser = at_least_one)]
+    pub every_nth: usize,
+
+    /// Name of the video, recorded next to the save and in reports. Defaults to the title in
+    /// the metadata of the --video file
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Frame rate of the source frames, to play them back in real time. Detected for --video
+    #[arg(long)]
+    pub source_fps: Option<f64>,
+
+    /// Frame rate to convert the source to, by repeating or dropping frames
+    #[arg(long)]
+    pub target_fps: Option<f64>,
+
+    /// Simulation rate to play back at, in ticks per second
+    #[arg(long, default_value_t = DEFAULT_TICK_RATE)]
+    pub tick_rate: f64,
+
+    /// Delay of each of the two timing delayers per frame, overriding the one derived from the
+    /// frame and tick rates
+    #[arg(long)]
+    pub half_period: Option<u32>,
+
+    /// Number of frames between chunking delayers
+    #[arg(long, default_value_t = CHUNK_INTERVAL, value_parser = at_least_one)]
+    pub chunk_interval: usize,
+
+    /// Fraction of the display that must change from one frame to the next to count as a scene
+    /// cut. Chunking delayers are moved onto nearby cuts
+    #[arg(long, value_parser = fraction)]
+    pub scene_cuts: Option<f64>,
+
+    /// Number of frames a lit pixel lingers for after going dark
+    #[arg(long, default_value_t = 0)]
+    pub phosphor_decay: usize,
+
+    /// Suppress pixels that flip for a single frame, delaying other changes by a frame
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// Defer pixel changes beyond this many per frame to the following frames, keeping the most
+    /// clustered changes first
+    #[arg(long, value_parser = at_least_one)]
+    pub max_changes_per_frame: Option<usize>,
+
+    /// Stretch brightness to the full range: off, frame or global
+    #[arg(long, default_value = "off")]
+    pub contrast_stretch: ContrastStretch,
+
+    /// Distance between row boards, in position units. Flat rows must be at least a board width
+    /// apart
+    #[arg(long)]
+    pub row_spacing: Option<i32>,
+
+    /// Lay row boards side by side instead of stacking them
+    #[arg(long)]
+    pub flat: bool,
+
+    /// Shift and recolor every other row board
+    #[arg(long)]
+    pub stagger_rows: bool,
+
+    /// Way pixel and chunking delayers face, seen from above: toward the sockets, away from
+    /// them, left or right
+    #[arg(long, default_value = "toward")]
+    pub facing: Facing,
+
+    /// Place every row on one shared board
+    #[arg(long, conflicts_with_all = ["flat", "row_spacing", "stagger_rows"])]
+    pub single_board: bool,
+
+    /// Curve stacked rows around a seat this far in front of the display's center, in position
+    /// units, turning every socket toward it
+    #[arg(long, conflicts_with_all = ["flat", "single_board"])]
+    pub curve_seat: Option<i32>,
+
+    /// Feed each row's pixel chain through junction pegs in line with the timing chain
+    #[arg(long)]
+    pub tidy_wiring: bool,
+
+    /// Extra ticks between the start pulse and the first frame, for getting into position
+    #[arg(long)]
+    pub start_delay: Option<u32>,
+
+    /// Count down from 3 on the display during the start delay
+    #[arg(long, requires = "start_delay")]
+    pub countdown: bool,
+
+    /// Extra ticks between a frame's timing pulse and its pixel updates
+    #[arg(long, default_value_t = 0)]
+    pub guard_ticks: u32,
+
+    /// What transparent parts of frames show: black, white, or keep to leave the display as it
+    /// was wherever frames are fully transparent, for overlays
+    #[arg(long, default_value = "black")]
+    pub alpha_background: AlphaBackground,
+
+    /// Draw frames filled in, or only the outlines of their shapes: fill or outline. Outlines
+    /// light and toggle far fewer pixels, and the threshold applies to the strength of each edge
+    #[arg(long, default_value = "fill")]
+    pub mode: Mode,
+
+    /// Luma, from 0 to 255, at and above which a pixel is lit. Ignored by sweep
+    #[arg(long, default_value_t = DEFAULT_THRESHOLD)]
+    pub threshold: u8,
+
+    /// Pick the threshold of each frame from its luma histogram, by Otsu's method, falling back
+    /// to --threshold for frames of a single shade
+    #[arg(long)]
+    pub auto_threshold: bool,
+
+    /// Light dark pixels and leave bright ones dark
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Dither shades of gray into patterns of lit pixels: none, floyd-steinberg, bayer or
+    /// blue-noise. Bayer and blue-noise patterns stay put from frame to frame, so they need
+    /// fewer pixel toggles
+    #[arg(long, default_value = "none", conflicts_with = "duotone")]
+    pub dither: Dither,
+
+    /// Show each pixel on two planes, for two-color displays: a dark plane lit from the
+    /// threshold up to this luma, and a bright plane lit from this luma up
+    #[arg(long)]
+    pub duotone: Option<u8>,
+
+    /// Add a column that is lit on even frames and dark on odd frames
+    #[arg(long)]
+    pub sync_column: bool,
+
+    /// Add a peg beside each row's timing chain at every chunk boundary, carrying the
+    /// frame-advance signal, for splicing in custom circuitry
+    #[arg(long)]
+    pub chunk_taps: bool,
+
+    /// Corner of the frame wired to the first pixel: bottom-left, bottom-right, top-left or
+    /// top-right
+    #[arg(long, default_value = "bottom-left")]
+    pub origin: Origin,
+
+    /// Mirror frames horizontally
+    #[arg(long)]
+    pub flip_h: bool,
+
+    /// Mirror frames vertically
+    #[arg(long)]
+    pub flip_v: bool,
+
+    /// Rotate frames clockwise by 0, 90, 180 or 270 degrees
+    #[arg(long, default_value = "0")]
+    pub rotate: Rotation,
+
+    /// Crop source frames to this rectangle, as `x,y,w,h`, before any other transformation
+    #[arg(long)]
+    pub crop: Option<Rect>,
+
+    /// Pad frames with dark bars to this aspect ratio, as `WIDTH:HEIGHT`, after rotating them
+    #[arg(long)]
+    pub letterbox: Option<Aspect>,
+
+    /// Resize frames to this width, keeping the aspect ratio unless --height is also given
+    #[arg(long, conflicts_with_all = ["scale", "pan_zoom"])]
+    pub width: Option<u32>,
+
+    /// Resize frames to this height, keeping the aspect ratio unless --width is also given
+    #[arg(long, conflicts_with_all = ["scale", "pan_zoom"])]
+    pub height: Option<u32>,
+
+    /// Resize frames by this factor, such as 0.25
+    #[arg(long, conflicts_with = "pan_zoom")]
+    pub scale: Option<f64>,
+
+    /// Filter used to resize frames: nearest, bilinear or lanczos
+    #[arg(long, default_value = "bilinear")]
+    pub filter: Filter,
+
+    /// Pan and zoom from one rectangle to another over the video, as `x,y,w,h:x,y,w,h`
+    #[arg(long, requires = "pan_size")]
+    pub pan_zoom: Option<PanZoom>,
+
+    /// Display size to scale the panned rectangle to, as `WIDTHxHEIGHT`
+    #[arg(long, requires = "pan_zoom")]
+    pub pan_size: Option<Size>,
+
+    /// Average each frame with the previous one
+    #[arg(long)]
+    pub blend: bool,
+
+    /// The display starts with every pixel lit instead of dark, so the first frame is painted
+    /// by turning off its dark pixels
+    #[arg(long)]
+    pub initially_lit: bool,
+
+    /// Show a test pattern before the video
+    #[arg(long)]
+    pub self_test: bool,
+
+    /// Frame whose display state is exported with --snapshot, counting any countdown and test
+    /// pattern frames
+    #[arg(long, requires = "snapshot")]
+    pub snapshot_frame: Option<usize>,
+
+    /// Export the display state at --snapshot-frame to this PNG file
+    #[arg(long, requires = "snapshot_frame")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Wire pixel delayers straight to the column instead of through junction pegs
+    #[arg(long)]
+    pub no_junction_pegs: bool,
+
+    /// Estimated size added to the save, in MiB, above which to warn
+    #[arg(long = "max-size", default_value_t = DEFAULT_MAX_SIZE_MIB)]
+    pub max_size_mib: u64,
+
+    /// Stop encoding as soon as the estimated size added to the save exceeds this many MiB
+    #[arg(long = "abort-size")]
+    pub abort_size_mib: Option<u64>,
+
+    /// Number of preprocessed frames to queue ahead of encoding
+    #[arg(long, default_value_t = DEFAULT_PREFETCH)]
+    pub prefetch: usize,
+
+    /// Split the frames into this many segments, encoded in parallel and then stitched together.
+    /// Not available with --video, whose decoder only reads forward
+    #[arg(long, default_value_t = 1, value_parser = at_least_one, conflicts_with = "video")]
+    pub segments: usize,
+
+    /// Number of threads decoding frames, shared between segments
+    #[arg(long, default_value_t = default_jobs(), value_parser = at_least_one)]
+    pub jobs: usize,
+}
+
+impl Options {
+    /// The options that determine how frames are encoded, once they have been read.
+    pub fn encoding(&self) -> encode::Options {
+        encode::Options {
+            tick_rate: self.tick_rate,
+            half_period: self.half_period,
+            start_delay: self.start_delay,
+            countdown: self.countdown,
+            self_test: self.self_test,
+            flip_h: self.flip_h,
+            flip_v: self.flip_v,
+            rotate: self.rotate,
+            crop: self.crop,
+            letterbox: self.letterbox,
+            width: self.width,
+            height: self.height,
+            scale: self.scale,
+            filter: self.filter,
+            pan_zoom: self.pan_zoom,
+            pan_size: self.pan_size,
+            contrast_stretch: self.contrast_stretch,
+            alpha_background: self.alpha_background,
+            blend: self.blend,
+            mode: self.mode,
+            origin: self.origin,
+            phosphor_decay: self.phosphor_decay,
+            denoise: self.denoise,
+            max_changes_per_frame: self.max_changes_per_frame,
+            threshold: self.threshold,
+            auto_threshold: self.auto_threshold,
+            invert: self.invert,
+            dither: self.dither,
+            duotone: self.duotone,
+            sync_column: self.sync_column,
+            scene_cuts: self.scene_cuts,
+            initially_lit: self.initially_lit,
+            row_spacing: self.row_spacing,
+            flat: self.flat,
+            stagger_rows: self.stagger_rows,
+            facing: self.facing,
+            single_board: self.single_board,
+            curve_seat: self.curve_seat,
+            tidy_wiring: self.tidy_wiring,
+            chunk_taps: self.chunk_taps,
+            no_junction_pegs: self.no_junction_pegs,
+            snapshot_frame: self.snapshot_frame,
+            snapshot: self.snapshot.clone(),
+            max_size_mib: self.max_size_mib,
+            abort_size_mib: self.abort_size_mib,
+            segments: self.segments,
+            jobs: self.jobs,
+            prefetch: self.prefetch,
+            title: self.title.clone(),
+        }
+    }
+}
+
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn at_least_one(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+fn fraction(s: &str) -> Result<f64, String> {
+    match s.parse() {
+        Ok(x) if x > 0.0 && x <= 1.0 => Ok(x),
+        Ok(_) => Err("must be above 0 and at most 1".to_owned()),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
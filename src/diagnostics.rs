@@ -0,0 +1,60 @@
+//! Suggestions for parameter choices that are likely to give a poor result, based on the
+//! encoded circuit and the same size model as [`estimate`](crate::estimate).
+
+/// Share of pixels changing on every frame above which the video is considered to flicker.
+const FLICKER_DENSITY: f64 = 0.2;
+
+/// Average toggles of one column within one chunk above which chunk nets stay large enough to
+/// slow down the game.
+const BUSY_CHUNK_TOGGLES: f64 = 40.0;
+
+/// Statistics of an encoded display, as inspected by [`suggestions`].
+pub struct Stats {
+    pub width: usize,
+    pub height: usize,
+    pub frames: usize,
+    pub toggles: usize,
+    /// Number of frames between chunking delayers.
+    pub chunk_interval: usize,
+    /// Estimated size added to the save, in bytes.
+    pub estimated_size: u64,
+    /// Size above which the save may not load, in bytes.
+    pub max_size: u64,
+}
+
+/// Returns actionable suggestions for the run described by `stats`, if any.
+pub fn suggestions(stats: &Stats) -> Vec<String> {
+    let mut suggestions = Vec::new();
+    let pixels = (stats.width * stats.height) as f64;
+    let frames = stats.frames.max(1) as f64;
+
+    let density = stats.toggles as f64 / (pixels * frames);
+    if density > FLICKER_DENSITY {
+        suggestions.push(format!(
+            "{:.0}% of pixels change on every frame; consider --blend to suppress flicker",
+            density * 100.0,
+        ));
+    }
+
+    let chunk_toggles =
+        stats.toggles as f64 / pixels * stats.chunk_interval.min(stats.frames) as f64 / frames;
+    if chunk_toggles > BUSY_CHUNK_TOGGLES {
+        suggestions.push(format!(
+            "columns toggle about {:.0} times per chunk, so the chunk interval of {} frames is too \
+             large for this motion level; consider --blend or a lower --tick-rate to drop frames",
+            chunk_toggles, stats.chunk_interval,
+        ));
+    }
+
+    if stats.estimated_size > stats.max_size {
+        let scale = (stats.max_size as f64 / stats.estimated_size as f64).sqrt();
+        suggestions.push(format!(
+            "consider a display of about {}x{} with --pan-zoom and --pan-size, or a lower \
+             --tick-rate to drop frames, to fit within the size limit",
+            (stats.width as f64 * scale) as usize,
+            (stats.height as f64 * scale) as usize,
+        ));
+    }
+
+    suggestions
+}
@@ -0,0 +1,98 @@
+//! Emission of a laid-out circuit into a target game or format, with the blotter sandbox of a
+//! Logic World save as the primary target.
+
+use anyhow::anyhow;
+use blotter::sandbox::{
+    component::{ChubbySocket, CircuitBoard, Delayer, Peg},
+    ComponentId, PegAddress, PegType, Sandbox,
+};
+
+use crate::{
+    circuit::{Circuit, Kind, Pin},
+    layout::Layout,
+};
+
+/// Something a laid-out circuit can be emitted into.
+pub trait Target {
+    /// Adds the boards, components and wires of `circuit`, positioned by `layout`.
+    fn emit(&mut self, circuit: &Circuit, layout: &dyn Layout) -> anyhow::Result<()>;
+}
+
+impl Target for Sandbox {
+    fn emit(&mut self, circuit: &Circuit, layout: &dyn Layout) -> anyhow::Result<()> {
+        emit_sandbox(self, circuit, layout)
+    }
+}
+
+fn emit_sandbox(
+    sandbox: &mut Sandbox,
+    circuit: &Circuit,
+    layout: &dyn Layout,
+) -> anyhow::Result<()> {
+    let boards: Vec<ComponentId> = layout
+        .boards()
+        .iter()
+        .map(|board| {
+            sandbox.add_component(
+                &CircuitBoard::new()
+                    .width(board.width)
+                    .height(board.height)
+                    .color(board.color)
+                    .build()
+                    .position(board.position),
+            )
+        })
+        .collect();
+
+    macro_rules! add_placed {
+        ($component:expr, $placement:expr) => {{
+            let component = $component
+                .parent(Some(boards[$placement.board]))
+                .position($placement.position);
+            match $placement.rotation {
+                Some(rotation) => sandbox.add_component(&component.rotation(rotation)),
+                None => sandbox.add_component(&component),
+            }
+        }};
+    }
+
+    let ids: Vec<ComponentId> = circuit
+        .components
+        .iter()
+        .map(|component| {
+            let placement = layout.place(component);
+            match component.kind {
+                Kind::Delayer { delay } => {
+                    add_placed!(Delayer::new().delay(delay).build(), placement)
+                }
+                Kind::Peg => add_placed!(Peg::new().build(), placement),
+                Kind::Socket => add_placed!(ChubbySocket::new().build(), placement),
+            }
+        })
+        .collect();
+
+    for wire in &circuit.wires {
+        sandbox
+            .add_wire(
+                peg_address(&ids, wire.from),
+                peg_address(&ids, wire.to),
+                0.0,
+            )
+            .map_err(|e| anyhow!("cannot add wire: {:?}", e))?;
+    }
+    Ok(())
+}
+
+/// Maps a pin role to the peg it corresponds to. Every emitted component kind has a single
+/// input at index 0, and delayers have a single output at index 0.
+fn peg_address(ids: &[ComponentId], pin: Pin) -> PegAddress {
+    let (node, peg_type) = match pin {
+        Pin::Input(node) => (node, PegType::Input),
+        Pin::Output(node) => (node, PegType::Output),
+    };
+    PegAddress {
+        component: ids[node.0],
+        peg_type,
+        peg_index: 0,
+    }
+}
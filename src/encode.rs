@@ -0,0 +1,593 @@
+//! Encoding of frames into a display driver circuit, independently of where the frames come
+//! from or where the circuit goes.
+
+use std::{path::PathBuf, thread, time::Duration};
+
+use anyhow::{anyhow, Context};
+use image::GenericImageView;
+
+use crate::{
+    cancel::CancellationToken,
+    circuit::{Circuit, Kind, NodeId, Pin, Slot},
+    diagnostics, estimate,
+    failure::Failure,
+    layout::{self, Dimensions, Facing, Layout, RowBoards, SingleBoard},
+    phases::PhaseTimes,
+    preprocess::{
+        self, AlphaBackground, Aspect, Bitmap, ContrastStretch, Dither, Filter, FrameDecoder,
+        FramePipeline, Mode, Origin, PanZoom, Rect, Rotation, Size, DEFAULT_THRESHOLD,
+    },
+    progress::{Observer, Phase},
+    segment::{self, Segment},
+    sidecar::Injection,
+    source::FrameSource,
+    timing::{self, DelayerChain, Timing, TimingGenerator},
+};
+
+/// Simulation rate the timing chain is tuned for, in ticks per second.
+///
+/// This reproduces the original fixed frame period of 20 ticks at 10 fps.
+pub const DEFAULT_TICK_RATE: f64 = 200.0;
+
+/// Estimated output size, in MiB, above which a warning is given.
+pub const DEFAULT_MAX_SIZE_MIB: u64 = 1024;
+
+/// Number of preprocessed frames that may be queued ahead of circuit generation.
+pub const DEFAULT_PREFETCH: usize = 16;
+
+/// How frames are turned into a circuit. The command-line options of the same names describe
+/// each field in more detail.
+#[derive(Clone)]
+pub struct Options {
+    /// Simulation rate that frame durations are converted at, in ticks per second.
+    pub tick_rate: f64,
+    /// Uniform timing delay that was chosen explicitly, overriding any frame durations.
+    pub half_period: Option<u32>,
+    /// Extra ticks before the first frame.
+    pub start_delay: Option<u32>,
+    /// Count down on the display during the start delay.
+    pub countdown: bool,
+    /// Show a test pattern before the frames.
+    pub self_test: bool,
+
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub rotate: Rotation,
+    pub crop: Option<Rect>,
+    pub letterbox: Option<Aspect>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub scale: Option<f64>,
+    pub filter: Filter,
+    pub pan_zoom: Option<PanZoom>,
+    /// Display size the pan/zoom rectangle is scaled to. Required with `pan_zoom`.
+    pub pan_size: Option<Size>,
+    pub contrast_stretch: ContrastStretch,
+    pub alpha_background: AlphaBackground,
+    pub blend: bool,
+    pub mode: Mode,
+    pub origin: Origin,
+    pub phosphor_decay: usize,
+    pub denoise: bool,
+    pub max_changes_per_frame: Option<usize>,
+    pub threshold: u8,
+    pub auto_threshold: bool,
+    pub invert: bool,
+    pub dither: Dither,
+    pub duotone: Option<u8>,
+    pub sync_column: bool,
+    /// Fraction of the pixels that must change from one frame to the next for the second to
+    /// start a new scene. When set, chunking delayers are moved onto nearby scene cuts.
+    pub scene_cuts: Option<f64>,
+    pub initially_lit: bool,
+
+    pub row_spacing: Option<i32>,
+    pub flat: bool,
+    pub stagger_rows: bool,
+    pub facing: Facing,
+    pub single_board: bool,
+    pub curve_seat: Option<i32>,
+    pub tidy_wiring: bool,
+    pub chunk_taps: bool,
+    pub no_junction_pegs: bool,
+
+    /// Frame whose display state is written to `snapshot`, as a PNG file.
+    pub snapshot_frame: Option<usize>,
+    pub snapshot: Option<PathBuf>,
+    pub max_size_mib: u64,
+    pub abort_size_mib: Option<u64>,
+
+    /// Number of segments encoded in parallel. Sources must support reading frames out of order
+    /// for more than one.
+    pub segments: usize,
+    /// Number of threads decoding frames, shared between segments.
+    pub jobs: usize,
+    pub prefetch: usize,
+
+    /// Name of the video, recorded with the injection.
+    pub title: Option<String>,
+}
+
+/// The defaults of the command-line options.
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tick_rate: DEFAULT_TICK_RATE,
+            half_period: None,
+            start_delay: None,
+            countdown: false,
+            self_test: false,
+            flip_h: false,
+            flip_v: false,
+            rotate: Rotation::None,
+            crop: None,
+            letterbox: None,
+            width: None,
+            height: None,
+            scale: None,
+            filter: Filter::Bilinear,
+            pan_zoom: None,
+            pan_size: None,
+            contrast_stretch: ContrastStretch::Off,
+            alpha_background: AlphaBackground::Black,
+            blend: false,
+            mode: Mode::Fill,
+            origin: Origin::BottomLeft,
+            phosphor_decay: 0,
+            denoise: false,
+            max_changes_per_frame: None,
+            threshold: DEFAULT_THRESHOLD,
+            auto_threshold: false,
+            invert: false,
+            dither: Dither::None,
+            duotone: None,
+            sync_column: false,
+            scene_cuts: None,
+            initially_lit: false,
+            row_spacing: None,
+            flat: false,
+            stagger_rows: false,
+            facing: Facing::Toward,
+            single_board: false,
+            curve_seat: None,
+            tidy_wiring: false,
+            chunk_taps: false,
+            no_junction_pegs: false,
+            snapshot_frame: None,
+            snapshot: None,
+            max_size_mib: DEFAULT_MAX_SIZE_MIB,
+            abort_size_mib: None,
+            segments: 1,
+            jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+            prefetch: DEFAULT_PREFETCH,
+            title: None,
+        }
+    }
+}
+
+/// Size of the frames of a source as decoded, and of the display they are shown on.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSize {
+    pub source: (u32, u32),
+    /// Size of the display, not counting planes or any sync column.
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Works out the size of the display for the frames of `source`, checking that the crop and
+/// pan/zoom rectangles lie within them.
+pub fn frame_size(options: &Options, source: &dyn FrameSource) -> anyhow::Result<FrameSize> {
+    if source.is_empty() {
+        return Err(anyhow!("there are no frames to encode").context(Failure::Frames));
+    }
+    let first_frame = source.frame(0).context(Failure::Frames)?;
+    let source_dimensions = first_frame.dimensions();
+    drop(first_frame);
+
+    if options
+        .crop
+        .is_some_and(|rect| !rect.fits_within(source_dimensions))
+    {
+        return Err(anyhow!("crop rectangle must lie within the frame").context(Failure::Arguments));
+    }
+    let framed_dimensions = preprocess::framed_dimensions(options, source_dimensions);
+    if let Some(pan_zoom) = &options.pan_zoom {
+        if !(pan_zoom.from.fits_within(framed_dimensions)
+            && pan_zoom.to.fits_within(framed_dimensions))
+        {
+            return Err(anyhow!("pan/zoom rectangles must lie within the frame")
+                .context(Failure::Arguments));
+        }
+    }
+
+    if options
+        .scale
+        .is_some_and(|scale| !scale.is_finite() || scale <= 0.0)
+        || options.width == Some(0)
+        || options.height == Some(0)
+    {
+        return Err(anyhow!("frames cannot be resized to nothing").context(Failure::Arguments));
+    }
+    let (width, height) = match options.pan_size {
+        Some(size) => (size.width, size.height),
+        None => {
+            preprocess::resized_dimensions(options, framed_dimensions).unwrap_or(framed_dimensions)
+        }
+    };
+
+    Ok(FrameSize {
+        source: source_dimensions,
+        width: width as usize,
+        height: height as usize,
+    })
+}
+
+/// Finds the frames that start a new scene, where at least `threshold` of the pixels differ from
+/// the previous frame. This reads all `source_frames` frames once before they are encoded.
+fn scene_cuts(
+    options: &Options,
+    decoder: &FrameDecoder,
+    leading_frames: &[Bitmap],
+    source_frames: usize,
+    (width, height): (usize, usize),
+    threshold: f64,
+) -> anyhow::Result<Vec<usize>> {
+    let mut pipeline = FramePipeline::new(options, width, height);
+    let mut cuts = Vec::new();
+    let mut last_frame: Option<Bitmap> = None;
+    for frame_index in 0..leading_frames.len() + source_frames {
+        let frame = match frame_index.checked_sub(leading_frames.len()) {
+            None => leading_frames[frame_index].clone(),
+            Some(source_index) => pipeline.process(source_index, decoder.decode(source_index)?),
+        };
+        if let Some(last_frame) = &last_frame {
+            if preprocess::changed_fraction(last_frame, &frame) >= threshold {
+                cuts.push(frame_index);
+            }
+        }
+        last_frame = Some(frame);
+    }
+    Ok(cuts)
+}
+
+/// A fully encoded display, not yet added to any save.
+pub struct Encoded {
+    pub circuit: Circuit,
+    pub layout: Box<dyn Layout>,
+    pub injection: Injection,
+    /// Estimated size added to the save, in bytes.
+    pub estimated_size: u64,
+    /// Number of junction pegs left out by wiring pixel delayers straight to their column.
+    pub junction_pegs_saved: usize,
+}
+
+/// Encodes the frames of `source` into a circuit, played back with `timing`. Sources that give
+/// the duration of each frame override its frame period, unless a half period is given.
+///
+/// Nothing is printed: `observer` is told of progress, and of problems that do not stop the
+/// encode, as they happen. The encode stops with [`Failure::Cancelled`] at the next frame
+/// boundary once `cancellation` is cancelled.
+pub fn encode(
+    options: &Options,
+    timing: &Timing,
+    source: &dyn FrameSource,
+    phases: &PhaseTimes,
+    observer: &dyn Observer,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Encoded> {
+    let FrameSize {
+        source: source_dimensions,
+        width,
+        height,
+    } = frame_size(options, source)?;
+
+    // The sync column is an extra column on the right of the display.
+    let display_width = preprocess::display_width(options, width);
+
+    // Frames that come before the video: a countdown spread over the start delay, then the
+    // test pattern at the uniform period.
+    let mut leading_frames = Vec::new();
+    let mut frame_periods = Vec::new();
+    if options.countdown {
+        leading_frames = preprocess::countdown(display_width, height, options.origin)
+            .ok_or_else(|| anyhow!("the countdown does not fit on the display"))
+            .context(Failure::Arguments)?;
+        let (start_delay, frames) = (
+            options.start_delay.unwrap_or(0),
+            leading_frames.len() as u32,
+        );
+        frame_periods
+            .extend((0..frames).map(|i| start_delay * (i + 1) / frames - start_delay * i / frames));
+    }
+    if options.self_test {
+        leading_frames.extend(preprocess::self_test(display_width, height));
+        frame_periods.resize(leading_frames.len(), timing.frame_period());
+    }
+    let frame_count = leading_frames.len() + source.len();
+
+    // Sources that say how long each frame lasts, such as GIFs, are played back with those
+    // timings, unless a period was chosen explicitly.
+    let durations: Option<Vec<Duration>> = (0..source.len())
+        .map(|index| source.duration(index))
+        .collect();
+    if let Some(durations) = durations.filter(|_| options.half_period.is_none()) {
+        frame_periods.extend(timing::frame_periods(&durations, options.tick_rate));
+    }
+    let timing = &Timing {
+        frame_periods,
+        ..timing.clone()
+    };
+    if let Some(snapshot_frame) = options.snapshot_frame {
+        if snapshot_frame >= frame_count {
+            return Err(anyhow!(
+                "snapshot frame {} is past the last frame {}",
+                snapshot_frame,
+                frame_count - 1
+            )
+            .context(Failure::Arguments));
+        }
+    }
+
+    // Two delayers for each frame (signal rise + fall)
+    let depth = frame_count * 2 + 1;
+
+    let dimensions = Dimensions {
+        width: display_width,
+        height,
+        depth,
+    };
+    let layout: Box<dyn Layout> = if options.single_board {
+        Box::new(SingleBoard::new(dimensions, options.facing).context(Failure::Placement)?)
+    } else {
+        Box::new(
+            RowBoards::new(
+                dimensions,
+                options.row_spacing,
+                options.flat,
+                options.stagger_rows,
+                options.facing,
+                options.curve_seat,
+            )
+            .context(Failure::Placement)?,
+        )
+    };
+
+    let decoder = FrameDecoder::new(options, phases, cancellation, source, source_dimensions)?;
+
+    // Where chunking delayers go decides how the timing chain compensates for them, so scene
+    // cuts have to be found before the chain is built.
+    let snapped_timing;
+    let timing = match options.scene_cuts {
+        Some(threshold) => {
+            let cuts = scene_cuts(
+                options,
+                &decoder,
+                &leading_frames,
+                source.len(),
+                (width, height),
+                threshold,
+            )?;
+            snapped_timing = Timing {
+                chunk_frames: Some(timing::snap_chunks_to_cuts(
+                    frame_count,
+                    timing.chunk_interval,
+                    &cuts,
+                )),
+                ..timing.clone()
+            };
+            &snapped_timing
+        }
+        None => timing,
+    };
+
+    let mut circuit = Circuit::new();
+
+    let timing_generator = DelayerChain::new(timing, depth);
+    let row_frame_delayers = timing_generator.build(&mut circuit, height);
+
+    let row_col_last_pegs: Vec<Vec<NodeId>> = (0..height)
+        .map(|y| {
+            (0..display_width)
+                .map(|x| circuit.add(Kind::Socket, Slot::Socket { row: y, column: x }))
+                .collect()
+        })
+        .collect();
+
+    // Segments are encoded on threads of their own, sharing the decoding threads between them.
+    let segments = options.segments.min(frame_count);
+    let progress = segment::Progress::new(&circuit, layout.boards().len());
+    let encoder = segment::Encoder {
+        options,
+        timing,
+        phases,
+        observer,
+        cancellation,
+        decoder: &decoder,
+        leading_frames: &leading_frames,
+        width,
+        height,
+        display_width,
+        frame_count,
+        circuit: &circuit,
+        row_frame_delayers: &row_frame_delayers,
+        jobs: (options.jobs / segments).max(1),
+        progress: &progress,
+    };
+    observer.on_phase_change(Phase::Encoding);
+    let segments = thread::scope(|scope| {
+        let encoder = &encoder;
+        let handles: Vec<_> = (0..segments)
+            .map(|segment| {
+                let frames =
+                    segment * frame_count / segments..(segment + 1) * frame_count / segments;
+                scope.spawn(move || encoder.encode(frames))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("segment encoder panicked"))
+            .collect::<anyhow::Result<Vec<Segment>>>()
+    })?;
+
+    // Each segment's column chains continue from where the previous segment left them.
+    let mut row_col_last_pegs = row_col_last_pegs;
+    let mut chunk_frames = Vec::new();
+    let mut toggles = 0;
+    let mut frame_hashes = Vec::with_capacity(frame_count);
+    let mut junction_pegs_saved = 0;
+    for segment in segments {
+        let renumbering = circuit.append(segment.circuit);
+        for (from, y, x) in segment.pending {
+            circuit.wire(renumbering.pin(from), Pin::Input(row_col_last_pegs[y][x]));
+        }
+        for (last_pegs, tails) in row_col_last_pegs.iter_mut().zip(&segment.tails) {
+            for (last_peg, tail) in last_pegs.iter_mut().zip(tails) {
+                if let Some(tail) = *tail {
+                    *last_peg = renumbering.node(tail);
+                }
+            }
+        }
+        chunk_frames.extend(segment.chunk_frames);
+        toggles += segment.toggles;
+        frame_hashes.extend(segment.frame_hashes);
+        junction_pegs_saved += segment.junction_pegs_saved;
+    }
+
+    let violations = timing::check_timing(
+        &timing_generator.arrivals(),
+        &chunk_frames,
+        frame_count,
+        timing,
+    );
+    for violation in &violations {
+        observer.on_warning(&format!(
+            "frame {} is displayed {} ticks after the previous frame, expected {}",
+            violation.frame,
+            violation.interval,
+            timing.period_of(violation.frame - 1),
+        ));
+    }
+
+    let estimated_size = estimate::file_size(&circuit, layout.boards().len());
+    if estimated_size > options.max_size_mib << 20 {
+        observer.on_warning(&format!(
+            "estimated output exceeds {} MiB and may not load in game",
+            options.max_size_mib
+        ));
+    }
+    let stats = diagnostics::Stats {
+        width: display_width,
+        height,
+        frames: frame_count,
+        toggles,
+        chunk_interval: timing.chunk_interval,
+        estimated_size,
+        max_size: options.max_size_mib << 20,
+    };
+    for suggestion in diagnostics::suggestions(&stats) {
+        observer.on_warning(&suggestion);
+    }
+
+    let bounds = layout::bounds(&layout.boards());
+    let injection = Injection {
+        width: display_width,
+        height,
+        frames: frame_count,
+        title: options.title.clone(),
+        toggles,
+        bounds,
+        frame_hashes,
+    };
+
+    Ok(Encoded {
+        circuit,
+        layout,
+        injection,
+        estimated_size,
+        junction_pegs_saved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+
+    /// A block moving across a speckled background, so that every frame changes some pixels.
+    fn frames() -> Vec<DynamicImage> {
+        (0..12)
+            .map(|frame| {
+                DynamicImage::ImageLuma8(GrayImage::from_fn(8, 4, |x, y| {
+                    let block = (x + 8 - frame % 8) % 8 < 3 && y > 0;
+                    let speckle = (x * 7 + y * 3 + frame * 5) % 11 == 0;
+                    Luma([if block != speckle { 255 } else { 0 }])
+                }))
+            })
+            .collect()
+    }
+
+    /// The components and wires of `encoded`, identified by slot, in a canonical order.
+    fn contents(encoded: &Encoded) -> (Vec<String>, Vec<String>) {
+        let circuit = &encoded.circuit;
+        let pin = |pin: Pin| match pin {
+            Pin::Input(node) => format!("input of {:?}", circuit.components[node.0].slot),
+            Pin::Output(node) => format!("output of {:?}", circuit.components[node.0].slot),
+        };
+        let mut components: Vec<String> = circuit
+            .components
+            .iter()
+            .map(|component| format!("{:?} at {:?}", component.kind, component.slot))
+            .collect();
+        let mut wires: Vec<String> = circuit
+            .wires
+            .iter()
+            .map(|wire| {
+                let mut ends = [pin(wire.from), pin(wire.to)];
+                ends.sort();
+                ends.join(" to ")
+            })
+            .collect();
+        components.sort();
+        wires.sort();
+        (components, wires)
+    }
+
+    fn encode_in_segments(segments: usize) -> Encoded {
+        let options = Options {
+            phosphor_decay: 1,
+            segments,
+            jobs: 2,
+            ..Options::default()
+        };
+        let timing = Timing {
+            chunk_interval: 5,
+            ..Timing::default()
+        };
+        encode(
+            &options,
+            &timing,
+            &frames(),
+            &PhaseTimes::default(),
+            &Quiet,
+            &CancellationToken::new(),
+        )
+        .unwrap()
+    }
+
+    struct Quiet;
+
+    impl Observer for Quiet {}
+
+    #[test]
+    fn segments_stitch_into_the_same_circuit() {
+        let whole = encode_in_segments(1);
+        let stitched = encode_in_segments(3);
+        assert_eq!(contents(&whole), contents(&stitched));
+        assert_eq!(
+            whole.injection.frame_hashes,
+            stitched.injection.frame_hashes
+        );
+        assert_eq!(whole.injection.toggles, stitched.injection.toggles);
+    }
+}
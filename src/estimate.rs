@@ -0,0 +1,56 @@
+//! Rough estimates of the size of a generated save.
+
+use crate::circuit::{Circuit, Kind};
+
+/// Approximate serialized size of a board, peg or socket: addresses, parent, type, position,
+/// rotation and peg state.
+const COMPONENT_BYTES: u64 = 64;
+
+/// Approximate size of the custom data a delayer carries on top of [`COMPONENT_BYTES`].
+const DELAYER_EXTRA_BYTES: u64 = 16;
+
+/// Approximate serialized size of a wire: two peg addresses, circuit state and rotation.
+const WIRE_BYTES: u64 = 32;
+
+fn component_bytes(kind: Kind) -> u64 {
+    match kind {
+        Kind::Delayer { .. } => COMPONENT_BYTES + DELAYER_EXTRA_BYTES,
+        Kind::Peg | Kind::Socket => COMPONENT_BYTES,
+    }
+}
+
+/// Estimates how many bytes `circuit` adds to the blotter file when emitted on `boards` boards.
+pub fn file_size(circuit: &Circuit, boards: usize) -> u64 {
+    let components: u64 = circuit
+        .components
+        .iter()
+        .map(|component| component_bytes(component.kind))
+        .sum();
+    components + boards as u64 * COMPONENT_BYTES + circuit.wires.len() as u64 * WIRE_BYTES
+}
+
+/// The same estimate as [`file_size`], kept up to date while a circuit is being built.
+///
+/// Components and wires are only ever appended to a circuit, so each update only looks at those
+/// added since the last one.
+#[derive(Default)]
+pub struct RunningEstimate {
+    components: usize,
+    wires: usize,
+    bytes: u64,
+}
+
+impl RunningEstimate {
+    /// Accounts for everything added to `circuit` since the last update, and returns the
+    /// estimate for it on `boards` boards.
+    pub fn update(&mut self, circuit: &Circuit, boards: usize) -> u64 {
+        self.bytes += circuit.components[self.components..]
+            .iter()
+            .map(|component| component_bytes(component.kind))
+            .sum::<u64>();
+        self.bytes += (circuit.wires.len() - self.wires) as u64 * WIRE_BYTES;
+        self.components = circuit.components.len();
+        self.wires = circuit.wires.len();
+        self.bytes + boards as u64 * COMPONENT_BYTES
+    }
+}
@@ -0,0 +1,51 @@
+//! Classes of failure, attached as context to errors.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Class of failure, which the command-line tool reports through the process exit code so that
+/// wrapper scripts can tell what went wrong. Errors outside these classes exit with code 1.
+/// Embedders can find it with [`anyhow::Error::downcast_ref`].
+///
+/// | Code | Failure                                                      |
+/// |------|--------------------------------------------------------------|
+/// | 2    | Invalid arguments or options                                 |
+/// | 3    | Frames could not be found, read or decoded                   |
+/// | 4    | The save or its sidecar could not be read or parsed          |
+/// | 5    | The display does not fit, or could not be placed in the save |
+/// | 6    | The save or its sidecar could not be written                 |
+/// | 130  | The run was interrupted, and the save was left unchanged     |
+#[derive(Clone, Copy, Debug)]
+pub enum Failure {
+    Arguments,
+    Frames,
+    SaveParse,
+    Placement,
+    Write,
+    Cancelled,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Arguments => 2,
+            Self::Frames => 3,
+            Self::SaveParse => 4,
+            Self::Placement => 5,
+            Self::Write => 6,
+            Self::Cancelled => 130,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Arguments => "invalid arguments",
+            Self::Frames => "cannot read frames",
+            Self::SaveParse => "cannot read save",
+            Self::Placement => "cannot place display",
+            Self::Write => "cannot write save",
+            Self::Cancelled => "interrupted",
+        })
+    }
+}
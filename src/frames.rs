@@ -0,0 +1,310 @@
+//! Discovery of the source frames, from a directory, a list of paths, an archive or an animated
+//! image.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{metadata, read_dir, read_to_string, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use glob::Pattern;
+use image::{gif::GifDecoder, png::PngDecoder, AnimationDecoder, DynamicImage, ImageFormat};
+use logicworld_badapple::source::FrameSource;
+
+use crate::remote;
+
+/// A source frame, either a file on disk or an image read out of an archive.
+pub enum Frame {
+    File(PathBuf),
+    Archived { name: String, data: Vec<u8> },
+}
+
+impl Frame {
+    pub fn open(&self) -> anyhow::Result<DynamicImage> {
+        let image = match self {
+            Self::File(path) => image::open(path)?,
+            Self::Archived { name, data } => {
+                image::load_from_memory_with_format(data, ImageFormat::from_path(name)?)?
+            }
+        };
+        Ok(image)
+    }
+}
+
+/// Frames found by [`find_frames`], [`read_frame_list`] or [`read_archive`], in playback order.
+pub struct FrameFiles(Vec<Frame>);
+
+impl FrameSource for FrameFiles {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        let frame = &self.0[index];
+        frame
+            .open()
+            .with_context(|| format!("cannot open frame {}", frame))
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{:?}", path),
+            Self::Archived { name, .. } => write!(f, "{:?}", name),
+        }
+    }
+}
+
+/// Entries skipped while looking for frames, by reason.
+#[derive(Default)]
+struct Skipped {
+    /// Only file names matching this are frames.
+    pattern: Option<Pattern>,
+    hidden: usize,
+    not_files: usize,
+    unreadable: usize,
+    not_images: usize,
+    unmatched: usize,
+}
+
+impl Skipped {
+    /// Checks the name of a regular file, counting it if it is not a frame.
+    fn is_frame(&mut self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if name.starts_with('.') {
+            self.hidden += 1;
+            false
+        } else if ImageFormat::from_path(path).is_err() {
+            self.not_images += 1;
+            false
+        } else if self
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.matches(&name))
+        {
+            self.unmatched += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn report(&self, source: &Path) {
+        let skipped =
+            self.hidden + self.not_files + self.unreadable + self.not_images + self.unmatched;
+        if skipped > 0 {
+            eprintln!(
+                "skipped {} entries in {:?}: {} hidden, {} not files, {} unreadable, {} not images, {} not matching the pattern",
+                skipped,
+                source,
+                self.hidden,
+                self.not_files,
+                self.unreadable,
+                self.not_images,
+                self.unmatched,
+            );
+        }
+    }
+}
+
+/// Lists the image files in `dir` whose names match `pattern`, if given, sorted by name.
+///
+/// Symbolic links are followed, and entries that are hidden, are not regular files, cannot be
+/// inspected, do not have an image extension or do not match are skipped, with a summary of
+/// what was skipped.
+pub fn find_frames(dir: &Path, pattern: Option<&Pattern>) -> anyhow::Result<FrameFiles> {
+    let mut frame_files = Vec::new();
+    let mut skipped = Skipped {
+        pattern: pattern.cloned(),
+        ..Skipped::default()
+    };
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        // Follows symbolic links, so broken links and link loops end up as unreadable.
+        match metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => {
+                skipped.not_files += 1;
+                continue;
+            }
+            Err(_) => {
+                skipped.unreadable += 1;
+                continue;
+            }
+        }
+        if skipped.is_frame(&path) {
+            frame_files.push(path);
+        }
+    }
+
+    skipped.report(dir);
+    if frame_files.is_empty() {
+        bail!("no frames found in {:?}", dir);
+    }
+    frame_files.sort();
+    Ok(FrameFiles(
+        frame_files.into_iter().map(Frame::File).collect(),
+    ))
+}
+
+/// Reads an ordered list of frame paths, one per line, from the file at `list`. Blank lines are
+/// ignored, and relative paths are relative to the directory containing the list.
+///
+/// The list and its entries may also be HTTP(S) URLs, which are downloaded into a local cache.
+/// Relative entries of a remote list are relative to the URL of the list.
+pub fn read_frame_list(list: &Path) -> anyhow::Result<FrameFiles> {
+    let list_url = list.to_str().filter(|list| remote::is_url(list));
+    let contents = match list_url {
+        Some(url) => read_to_string(remote::fetch(url)?),
+        None => read_to_string(list),
+    }
+    .with_context(|| format!("cannot read frame list {:?}", list))?;
+
+    let base = list.parent().unwrap_or(Path::new(""));
+    let frames = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let path = match list_url {
+                Some(url) => remote::fetch(&remote::join(url, line))?,
+                None if remote::is_url(line) => remote::fetch(line)?,
+                None => base.join(line),
+            };
+            Ok(Frame::File(path))
+        })
+        .collect::<anyhow::Result<Vec<Frame>>>()?;
+    if frames.is_empty() {
+        bail!("no frames listed in {:?}", list);
+    }
+    Ok(FrameFiles(frames))
+}
+
+/// Reads the frames out of a `.zip` or `.tar` archive into memory, sorted by entry name.
+///
+/// Entries are skipped by the same rules as frames in a directory, with `pattern` matched
+/// against the file name of each entry.
+pub fn read_archive(archive: &Path, pattern: Option<&Pattern>) -> anyhow::Result<FrameFiles> {
+    let file = File::open(archive)?;
+    let mut entries = Vec::new();
+    let mut skipped = Skipped {
+        pattern: pattern.cloned(),
+        ..Skipped::default()
+    };
+    let mut add = |name: String, reader: &mut dyn Read| -> anyhow::Result<()> {
+        if skipped.is_frame(Path::new(&name)) {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            entries.push((name, data));
+        }
+        Ok(())
+    };
+
+    match archive.extension().and_then(|extension| extension.to_str()) {
+        Some("zip") => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                add(entry.name().to_owned(), &mut entry)?;
+            }
+        }
+        Some("tar") => {
+            let mut tar = tar::Archive::new(file);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().into_owned();
+                add(name, &mut entry)?;
+            }
+        }
+        _ => bail!("{:?}: archive must be a .zip or .tar file", archive),
+    }
+
+    skipped.report(archive);
+    if entries.is_empty() {
+        bail!("no frames found in {:?}", archive);
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(FrameFiles(
+        entries
+            .into_iter()
+            .map(|(name, data)| Frame::Archived { name, data })
+            .collect(),
+    ))
+}
+
+/// Frames of an animated image, decoded into memory along with how long each is shown.
+pub struct Animation {
+    frames: Vec<DynamicImage>,
+    delays: Vec<Duration>,
+}
+
+impl FrameSource for Animation {
+    fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        Ok(self.frames[index].clone())
+    }
+
+    fn duration(&self, index: usize) -> Option<Duration> {
+        Some(self.delays[index])
+    }
+}
+
+/// Decodes every frame of an animated GIF or PNG (APNG), composited onto the full canvas. The
+/// format is chosen by the file extension, `.gif`, `.png` or `.apng`.
+///
+/// Frames without a delay are shown for 100 ms, as web browsers do.
+pub fn read_animation(path: &Path) -> anyhow::Result<Animation> {
+    let reader = BufReader::new(File::open(path)?);
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gif") => collect_animation(GifDecoder::new(reader)?, path),
+        Some("png" | "apng") => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng() {
+                bail!("{:?} is not an animated PNG", path);
+            }
+            collect_animation(decoder.apng(), path)
+        }
+        _ => bail!("{:?} is not a GIF or PNG file", path),
+    }
+}
+
+fn collect_animation<'a>(
+    decoder: impl AnimationDecoder<'a>,
+    path: &Path,
+) -> anyhow::Result<Animation> {
+    let mut frames = Vec::new();
+    let mut delays = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay = Duration::from_secs_f64(f64::from(numerator) / f64::from(denominator) / 1000.0);
+        delays.push(if delay.is_zero() {
+            Duration::from_millis(100)
+        } else {
+            delay
+        });
+        frames.push(DynamicImage::ImageRgba8(frame.into_buffer()));
+    }
+    if frames.is_empty() {
+        bail!("no frames found in {:?}", path);
+    }
+    Ok(Animation { frames, delays })
+}
@@ -0,0 +1,432 @@
+//! Layout engines, which assign boards, positions and rotations to the slots of a circuit.
+
+use std::{f32::consts::FRAC_1_SQRT_2, str::FromStr};
+
+use anyhow::bail;
+
+use crate::circuit::{Component, Kind, Slot};
+
+/// Rotation applied to sockets, and by default to pixel and chunking delayers, half a turn
+/// about Y.
+const FLIPPED: [f32; 4] = [0.0, 1.0, 0.0, 0.0];
+
+/// Which way pixel and chunking delayers face, as seen from above their board.
+#[derive(Clone, Copy)]
+pub enum Facing {
+    /// Turned half a turn, with their outputs toward the sockets at the front of the board.
+    Toward,
+    /// In their default orientation, with their outputs away from the sockets.
+    Away,
+    /// Turned a quarter turn counterclockwise.
+    Left,
+    /// Turned a quarter turn clockwise.
+    Right,
+}
+
+impl Facing {
+    fn rotation(self) -> Option<[f32; 4]> {
+        match self {
+            Self::Toward => Some(FLIPPED),
+            Self::Away => None,
+            Self::Left => Some([0.0, -FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2]),
+            Self::Right => Some([0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2]),
+        }
+    }
+}
+
+impl FromStr for Facing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toward" => Ok(Self::Toward),
+            "away" => Ok(Self::Away),
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            _ => bail!("unknown facing {:?}", s),
+        }
+    }
+}
+
+/// Size of one board square, in position units.
+const SQUARE: i32 = 300;
+
+/// Color of the generated boards.
+const BOARD_COLOR: [u8; 3] = [51, 51, 51];
+
+/// Color of every other row board when rows are staggered.
+const ALTERNATE_COLOR: [u8; 3] = [38, 56, 82];
+
+/// Offset along Z of every other row board when rows are staggered, in position units.
+const STAGGER: i32 = 2 * SQUARE;
+
+/// A circuit board to place the generated components on. Sizes are in board squares.
+pub struct Board {
+    pub width: u32,
+    pub height: u32,
+    pub position: [i32; 3],
+    pub color: [u8; 3],
+}
+
+/// An axis-aligned box, from the minimum corner to the maximum corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+}
+
+impl Bounds {
+    /// Whether the two boxes share any volume. Boxes that only touch do not intersect.
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        (0..3).all(|axis| self.min[axis] < other.max[axis] && other.min[axis] < self.max[axis])
+    }
+}
+
+/// Returns the box covering the surfaces of all boards.
+pub fn bounds(boards: &[Board]) -> Bounds {
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for board in boards {
+        let far = [
+            board.position[0] + board.width as i32 * SQUARE,
+            board.position[1],
+            board.position[2] + board.height as i32 * SQUARE,
+        ];
+        let axes = min.iter_mut().zip(&mut max).zip(&board.position).zip(far);
+        for (((low, high), &near), far) in axes {
+            *low = (*low).min(near);
+            *high = (*high).max(far);
+        }
+    }
+    Bounds { min, max }
+}
+
+pub struct Placement {
+    /// Index into [`Layout::boards`] of the board the component is placed on.
+    pub board: usize,
+    /// Position relative to the board.
+    pub position: [i32; 3],
+    /// Rotation relative to the board, or `None` to keep the component's default orientation.
+    pub rotation: Option<[f32; 4]>,
+}
+
+pub trait Layout {
+    fn boards(&self) -> Vec<Board>;
+    fn place(&self, component: &Component) -> Placement;
+}
+
+/// Size of the display driver: display columns and rows, and length of the timing chain.
+#[derive(Clone, Copy)]
+pub struct Dimensions {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+}
+
+impl Dimensions {
+    /// Size in board squares of the area covering one row.
+    fn row_size(&self) -> anyhow::Result<(u32, u32)> {
+        let width = 1 + 3 * u32::try_from(self.width)?;
+        let depth = 2 * u32::try_from(self.depth)?;
+        Ok((width, depth))
+    }
+}
+
+/// Position and rotation of a component within the area covering its row.
+///
+/// Timing delayers run along Z at the left edge, with each column's pixel delayers, junctions
+/// and socket in a lane to its right.
+fn row_placement(component: &Component, facing: Facing) -> ([i32; 3], Option<[f32; 4]>) {
+    let rotation = match component.kind {
+        Kind::Peg => None,
+        Kind::Delayer { .. } => facing.rotation(),
+        Kind::Socket => Some(FLIPPED),
+    };
+    let position = match component.slot {
+        Slot::Timing { z, .. } => return ([150, 150, z as i32 * 600 + 150], None),
+        Slot::RowBus { z, .. } => return ([150, 150, z as i32 * 600 - 150], None),
+        Slot::ChunkTap { z, .. } => return ([450, 150, z as i32 * 600 + 150], None),
+        Slot::Socket { column, .. } => [column as i32 * 900 + 750, 150, 150],
+        Slot::Junction { column, z, .. } => [column as i32 * 900 + 750, 150, z as i32 * 600 - 450],
+        Slot::Pixel { column, z, .. } => [column as i32 * 900 - 450, 150, z as i32 * 600 - 150],
+    };
+    (position, rotation)
+}
+
+/// Placement of the columns of a display curved around a seat in front of it.
+struct Curve {
+    /// Offset along Z of each column's lane, in position units.
+    offsets: Vec<i32>,
+    /// Rotation of each column's socket, turning it toward the seat.
+    rotations: Vec<[f32; 4]>,
+}
+
+impl Curve {
+    /// Puts the sockets of `width` columns on an arc around a seat `distance` units in front of
+    /// the center of the display. The outermost columns stay in place and the others are pushed
+    /// back, so that every socket is equally far from the seat.
+    fn new(width: usize, distance: i32) -> anyhow::Result<Self> {
+        if distance <= 0 {
+            bail!("the seat must be in front of the display");
+        }
+        let distance = f64::from(distance);
+        let column_spacing = 900.0;
+        let center = (width as f64 - 1.0) / 2.0;
+        let radius = (center * column_spacing).hypot(distance);
+        let (offsets, rotations) = (0..width)
+            .map(|column| {
+                let across = (column as f64 - center) * column_spacing;
+                let depth = (radius * radius - across * across).sqrt();
+                // Half a turn, as for flat sockets, and then toward the seat.
+                let half_angle = across.atan2(depth) / 2.0;
+                (
+                    (depth - distance).round() as i32,
+                    [0.0, half_angle.cos() as f32, 0.0, -half_angle.sin() as f32],
+                )
+            })
+            .unzip();
+        Ok(Self { offsets, rotations })
+    }
+
+    /// Moves a component of a column's lane onto the curve.
+    fn apply(
+        &self,
+        component: &Component,
+        position: &mut [i32; 3],
+        rotation: &mut Option<[f32; 4]>,
+    ) {
+        let column = match component.slot {
+            Slot::Socket { column, .. } => {
+                *rotation = Some(self.rotations[column]);
+                column
+            }
+            Slot::Junction { column, .. } | Slot::Pixel { column, .. } => column,
+            // Chunk taps sit in the pixel lane of the second column, so they move back with it.
+            Slot::ChunkTap { .. } if self.offsets.len() > 1 => 1,
+            Slot::Timing { .. } | Slot::RowBus { .. } | Slot::ChunkTap { .. } => return,
+        };
+        position[2] += self.offsets[column];
+    }
+}
+
+/// One board per row. Rows are stacked vertically by default, or laid out side by side along X
+/// in flat mode.
+///
+/// Staggered rows shift every other board along Z and give it a different color, so that
+/// neighbouring rows can be told apart in game. Stacked rows can be curved around a seat, with
+/// each column moved back along Z.
+pub struct RowBoards {
+    dimensions: Dimensions,
+    row_size: (u32, u32),
+    spacing: i32,
+    flat: bool,
+    stagger: bool,
+    facing: Facing,
+    curve: Option<Curve>,
+}
+
+impl RowBoards {
+    /// Creates the layout. Without an explicit spacing, stacked rows are 900 units apart and
+    /// flat rows are placed edge to edge. A spacing that would make row boards overlap is an
+    /// error. With a seat distance, the display is curved around a seat that far in front of it,
+    /// and row boards are deepened to hold the curve.
+    pub fn new(
+        dimensions: Dimensions,
+        spacing: Option<i32>,
+        flat: bool,
+        stagger: bool,
+        facing: Facing,
+        seat_distance: Option<i32>,
+    ) -> anyhow::Result<Self> {
+        let mut row_size = dimensions.row_size()?;
+        if flat && seat_distance.is_some() {
+            bail!("only stacked rows can be curved");
+        }
+        let curve = seat_distance
+            .map(|distance| Curve::new(dimensions.width, distance))
+            .transpose()?;
+        if let Some(curve) = &curve {
+            let deepest = curve.offsets.iter().copied().max().unwrap_or(0);
+            row_size.1 += u32::try_from(deepest)?.div_ceil(SQUARE as u32);
+        }
+        // Flat rows must not overlap, and stacked rows must leave room for the components
+        // standing on the board below.
+        let min_spacing = if flat {
+            i32::try_from(row_size.0)? * SQUARE
+        } else {
+            SQUARE
+        };
+        let spacing = match spacing {
+            Some(spacing) if spacing < min_spacing => {
+                bail!(
+                    "row boards must be at least {} units apart, but the spacing is {}",
+                    min_spacing,
+                    spacing
+                )
+            }
+            Some(spacing) => spacing,
+            None if flat => min_spacing,
+            None => 900,
+        };
+        Ok(Self {
+            dimensions,
+            row_size,
+            spacing,
+            flat,
+            stagger,
+            facing,
+            curve,
+        })
+    }
+}
+
+impl Layout for RowBoards {
+    fn boards(&self) -> Vec<Board> {
+        (0..self.dimensions.height)
+            .map(|y| {
+                let offset = y as i32 * self.spacing;
+                let alternate = self.stagger && y % 2 == 1;
+                let z = if alternate { STAGGER } else { 0 };
+                Board {
+                    width: self.row_size.0,
+                    height: self.row_size.1,
+                    position: if self.flat {
+                        [offset, 0, z]
+                    } else {
+                        [0, offset, z]
+                    },
+                    color: if alternate {
+                        ALTERNATE_COLOR
+                    } else {
+                        BOARD_COLOR
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn place(&self, component: &Component) -> Placement {
+        let (mut position, mut rotation) = row_placement(component, self.facing);
+        if let Some(curve) = &self.curve {
+            curve.apply(component, &mut position, &mut rotation);
+        }
+        Placement {
+            board: component.slot.row(),
+            position,
+            rotation,
+        }
+    }
+}
+
+/// All rows side by side on one shared board.
+pub struct SingleBoard {
+    board_width: u32,
+    board_depth: u32,
+    row_width: i32,
+    facing: Facing,
+}
+
+impl SingleBoard {
+    pub fn new(dimensions: Dimensions, facing: Facing) -> anyhow::Result<Self> {
+        let (row_width, board_depth) = dimensions.row_size()?;
+        let board_width = row_width
+            .checked_mul(u32::try_from(dimensions.height)?)
+            .ok_or_else(|| anyhow::anyhow!("display is too wide to fit on a single board"))?;
+        Ok(Self {
+            board_width,
+            board_depth,
+            row_width: i32::try_from(row_width)? * SQUARE,
+            facing,
+        })
+    }
+}
+
+impl Layout for SingleBoard {
+    fn boards(&self) -> Vec<Board> {
+        vec![Board {
+            width: self.board_width,
+            height: self.board_depth,
+            position: [0, 0, 0],
+            color: BOARD_COLOR,
+        }]
+    }
+
+    fn place(&self, component: &Component) -> Placement {
+        let (position, rotation) = row_placement(component, self.facing);
+        let origin = component.slot.row() as i32 * self.row_width;
+        Placement {
+            board: 0,
+            position: [origin + position[0], position[1], position[2]],
+            rotation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_boards_cannot_overlap() {
+        let dimensions = Dimensions {
+            width: 4,
+            height: 3,
+            depth: 10,
+        };
+        let flat = |spacing| RowBoards::new(dimensions, spacing, true, false, Facing::Toward, None);
+        let stacked =
+            |spacing| RowBoards::new(dimensions, spacing, false, false, Facing::Toward, None);
+        // A flat row board is 13 squares wide.
+        assert_eq!(flat(None).unwrap().spacing, 13 * SQUARE);
+        assert!(flat(Some(13 * SQUARE)).is_ok());
+        assert!(flat(Some(12 * SQUARE)).is_err());
+        assert!(stacked(Some(SQUARE)).is_ok());
+        assert!(stacked(Some(0)).is_err());
+        assert!(stacked(Some(-900)).is_err());
+    }
+
+    #[test]
+    fn curved_rows_keep_components_apart() {
+        let dimensions = Dimensions {
+            width: 5,
+            height: 2,
+            depth: 12,
+        };
+        let layout =
+            RowBoards::new(dimensions, None, false, false, Facing::Toward, Some(2000)).unwrap();
+        // Frames take the even timing steps from 2 on.
+        let frame_steps = || (2..dimensions.depth).step_by(2);
+        let mut components = Vec::new();
+        for row in 0..dimensions.height {
+            for z in 0..dimensions.depth {
+                components.push((Kind::Delayer { delay: 1 }, Slot::Timing { row, z }));
+            }
+            for z in frame_steps() {
+                components.push((Kind::Peg, Slot::RowBus { row, z }));
+                components.push((Kind::Peg, Slot::ChunkTap { row, z }));
+            }
+            for column in 0..dimensions.width {
+                components.push((Kind::Socket, Slot::Socket { row, column }));
+                for z in frame_steps() {
+                    components.push((Kind::Peg, Slot::Junction { row, column, z }));
+                    components.push((Kind::Delayer { delay: 1 }, Slot::Pixel { row, column, z }));
+                }
+            }
+        }
+
+        let placed: Vec<(Slot, Placement)> = components
+            .into_iter()
+            .map(|(kind, slot)| (slot, layout.place(&Component { kind, slot })))
+            .collect();
+        for (i, (slot, placement)) in placed.iter().enumerate() {
+            for (other_slot, other) in &placed[i + 1..] {
+                let [x, y, z] = placement.position;
+                let [other_x, other_y, other_z] = other.position;
+                let overlaps = placement.board == other.board
+                    && (x, y) == (other_x, other_y)
+                    && z.abs_diff(other_z) < SQUARE as u32;
+                assert!(!overlaps, "{:?} overlaps {:?}", slot, other_slot);
+            }
+        }
+    }
+}
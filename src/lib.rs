@@ -0,0 +1,22 @@
+//! Generation of Logic World display drivers that play back a video.
+
+pub mod audit;
+pub mod cancel;
+pub mod circuit;
+pub mod diagnostics;
+pub mod emit;
+pub mod encode;
+pub mod estimate;
+pub mod failure;
+pub mod layout;
+pub mod lock;
+pub mod phases;
+pub mod plan;
+pub mod preprocess;
+pub mod progress;
+pub mod schematic;
+pub mod segment;
+pub mod sidecar;
+pub mod source;
+pub mod ticker;
+pub mod timing;
@@ -0,0 +1,122 @@
+//! Lockfiles pinning everything that determines the generated circuit, so that collaborators
+//! can regenerate byte-identical worlds.
+//!
+//! Like the [sidecar](crate::sidecar), the file uses `Key: value` lines, with one `Parameter`
+//! line for each effective parameter.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+
+use crate::sidecar;
+
+/// What a run was generated from.
+pub struct Lock {
+    pub tool_version: String,
+    /// Effective value of every parameter affecting the circuit, as sorted `name=value` pairs.
+    pub parameters: Vec<String>,
+    /// Number of displayed frames.
+    pub frames: usize,
+    /// Digest of the content hashes of every displayed frame. See [`frames_digest`].
+    pub frames_digest: u64,
+}
+
+/// Combines the content hashes of displayed frames, as given by
+/// [`frame_hash`](crate::sidecar::frame_hash), into one.
+pub fn frames_digest(frame_hashes: &[u64]) -> u64 {
+    sidecar::fnv1a(frame_hashes.iter().flat_map(|hash| hash.to_le_bytes()))
+}
+
+impl Lock {
+    /// Describes a run of this version of the tool.
+    pub fn new(parameters: Vec<String>, frame_hashes: &[u64]) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            parameters,
+            frames: frame_hashes.len(),
+            frames_digest: frames_digest(frame_hashes),
+        }
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut lock = Self {
+            tool_version: String::new(),
+            parameters: Vec::new(),
+            frames: 0,
+            frames_digest: 0,
+        };
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once(": ") else {
+                continue;
+            };
+            match key {
+                "ToolVersion" => lock.tool_version = value.to_string(),
+                "Parameter" => lock.parameters.push(value.to_string()),
+                "Frames" => lock.frames = value.parse().context("malformed frame count")?,
+                "FramesDigest" => {
+                    lock.frames_digest =
+                        u64::from_str_radix(value, 16).context("malformed frames digest")?
+                }
+                _ => {}
+            }
+        }
+        Ok(lock)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "ToolVersion: {}", self.tool_version)?;
+        for parameter in &self.parameters {
+            writeln!(writer, "Parameter: {}", parameter)?;
+        }
+        writeln!(writer, "Frames: {}", self.frames)?;
+        writeln!(writer, "FramesDigest: {:016x}", self.frames_digest)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Fails, listing the differences, unless this version of the tool and `parameters` match
+    /// the lock. Frames are only known after encoding, and are checked separately with
+    /// [`check_frames`](Self::check_frames).
+    pub fn check_parameters(&self, parameters: &[String]) -> anyhow::Result<()> {
+        let mut differences = Vec::new();
+        let tool_version = env!("CARGO_PKG_VERSION");
+        if tool_version != self.tool_version {
+            differences.push(format!(
+                "tool version {} instead of {}",
+                tool_version, self.tool_version
+            ));
+        }
+        for parameter in parameters {
+            if !self.parameters.contains(parameter) {
+                differences.push(format!("{} is not locked", parameter));
+            }
+        }
+        for parameter in &self.parameters {
+            if !parameters.contains(parameter) {
+                differences.push(format!("{} is locked but not given", parameter));
+            }
+        }
+        if !differences.is_empty() {
+            bail!("run differs from the lockfile: {}", differences.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Fails unless the displayed frames, given by their content hashes, match the lock.
+    pub fn check_frames(&self, frame_hashes: &[u64]) -> anyhow::Result<()> {
+        if (frame_hashes.len(), frames_digest(frame_hashes)) != (self.frames, self.frames_digest) {
+            bail!(
+                "the {} displayed frames differ from the {} locked ones",
+                frame_hashes.len(),
+                self.frames
+            );
+        }
+        Ok(())
+    }
+}
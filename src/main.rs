@@ -1,272 +1,805 @@
+mod cli;
+mod frames;
+mod remote;
+mod video;
+mod y4m;
+
 use std::{
     env::args_os,
-    fs::{read_dir, File},
-    io::{BufReader, BufWriter, Write},
+    ffi::OsString,
+    fs::{copy, create_dir_all, remove_file, rename, File},
+    io::{stdin, stdout, BufReader, BufWriter, Cursor, Read, Write},
     path::{Path, PathBuf},
     process::exit,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, bail};
-use blotter::{
-    sandbox::{
-        component::{ChubbySocket, CircuitBoard, Delayer, Peg},
-        ComponentId, PegAddress, PegType, Sandbox,
-    },
-    BlotterFile,
+use anyhow::{anyhow, bail, Context};
+use blotter::{sandbox::Sandbox, BlotterFile};
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use cli::{Cli, Command, InjectArgs, ManArgs, Options, PlanArgs, SweepArgs};
+use logicworld_badapple::{
+    audit::{self, Problem},
+    cancel::CancellationToken,
+    diagnostics,
+    emit::Target,
+    encode::{self, Encoded},
+    failure::Failure,
+    layout::Bounds,
+    lock::Lock,
+    phases::PhaseTimes,
+    plan::{self, Plan},
+    preprocess::{self, Bitmap, Decoded, FrameDecoder, FramePipeline},
+    progress::{Observer, Phase, Totals},
+    schematic::JsonSchematic,
+    sidecar::{self, Injection, Record},
+    source::{Decimated, FrameSource, Resampled, Trimmed},
+    ticker::Ticker,
+    timing::{Timing, MIN_HALF_PERIOD},
 };
-use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgb, Rgba};
-
-fn main() -> anyhow::Result<()> {
-    let path = match args_os().nth(1) {
-        Some(x) => x,
-        None => {
-            eprintln!("missing argument `path`");
-            exit(1);
+use video::VideoFile;
+use y4m::Y4m;
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("Error: {:?}", error);
+        let code = error
+            .downcast_ref::<Failure>()
+            .map_or(1, |failure| failure.exit_code());
+        exit(code);
+    }
+}
+
+/// Reports progress on stderr.
+struct Console;
+
+impl Observer for Console {
+    fn on_totals(&self, totals: &Totals) {
+        eprintln!(
+            "{}: {} components, {} wires, about {} MiB",
+            totals.frame,
+            totals.components,
+            totals.wires,
+            totals.estimated_size >> 20,
+        );
+    }
+
+    fn on_warning(&self, message: &str) {
+        eprintln!("warning: {}", message);
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    match cli.command {
+        Command::Inject(args) => {
+            let (_, matches) = matches.subcommand().expect("a command was parsed");
+            inject(&args, matches)
         }
+        Command::Estimate(options) => report_estimate(&options),
+        Command::Sweep(args) => sweep(&args),
+        Command::Plan(args) => report_plan(&args),
+        Command::Clean => remote::clean(),
+        Command::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut stdout());
+            Ok(())
+        }
+        Command::Man(args) => write_man_pages(&args),
+    }
+}
+
+/// Prints the man page of the tool, or writes it into `--dir` along with one page for each
+/// command, named `<tool>-<command>.1`.
+fn write_man_pages(args: &ManArgs) -> anyhow::Result<()> {
+    let command = Cli::command();
+    let Some(dir) = &args.dir else {
+        let mut out = stdout().lock();
+        clap_mangen::Man::new(command).render(&mut out)?;
+        return Ok(out.flush()?);
     };
+    create_dir_all(dir)?;
+    let name = command.get_name().to_string();
+    let mut pages = vec![(name.clone(), command.clone())];
+    for subcommand in command.get_subcommands() {
+        let page = format!("{}-{}", name, subcommand.get_name());
+        let usage = format!("{} {}", name, subcommand.get_name());
+        pages.push((
+            page.clone(),
+            subcommand.clone().display_name(page).bin_name(usage),
+        ));
+    }
+    for (page, command) in pages {
+        let path = dir.join(format!("{}.1", page));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        clap_mangen::Man::new(command).render(&mut writer)?;
+        writer.flush()?;
+        eprintln!("wrote {}", path.display());
+    }
+    Ok(())
+}
 
-    let mut reader = BufReader::new(File::open(&path)?);
-    let file = BlotterFile::read(&mut reader)
-        .map_err(|e| anyhow!("cannot parse blotter file: {:?}", e))?;
+/// Stops the run at the next frame boundary, before anything is written, when interrupted.
+fn cancel_on_interrupt() -> anyhow::Result<CancellationToken> {
+    let cancellation = CancellationToken::new();
+    let handler_cancellation = cancellation.clone();
+    ctrlc::set_handler(move || handler_cancellation.cancel())?;
+    Ok(cancellation)
+}
 
-    let mut sandbox = Sandbox::from(&file.migrate());
-    inject(&mut sandbox)?;
-    let file = BlotterFile::V6((&sandbox).into());
+fn report_estimate(options: &Options) -> anyhow::Result<()> {
+    let observer = Console;
+    let cancellation = cancel_on_interrupt()?;
+    let timing = configure_timing(options).context(Failure::Arguments)?;
+    let phases = PhaseTimes::default();
 
-    let mut writer = BufWriter::new(File::create(&path)?);
-    file.write(&mut writer)
-        .map_err(|e| anyhow!("cannot write blotter file: {:?}", e))?;
-    writer.flush()?;
+    let encoded = encode_input(options, &timing, &phases, &observer, &cancellation)?;
+    if cancellation.is_cancelled() {
+        return Err(anyhow!(Failure::Cancelled));
+    }
+    encoded.circuit.validate().context(Failure::Placement)?;
 
+    report_display(&encoded.injection);
+    phases.report();
     Ok(())
 }
 
-fn inject(sandbox: &mut Sandbox) -> anyhow::Result<()> {
-    let frames_dir = Path::new("frames");
-    let mut frame_files: Vec<PathBuf> = read_dir(frames_dir)?
-        .map(|result| result.map(|dir_entry| dir_entry.path()))
-        .collect::<Result<_, _>>()?;
-    frame_files.sort();
-
-    let first_frame = image::open(&frame_files[0])?;
-    let width = first_frame.width() as usize;
-    let height = first_frame.height() as usize;
-    drop(first_frame);
-
-    // Two delayers for each frame (signal rise + fall)
-    let depth = frame_files.len() * 2 + 1;
-
-    let board_width: u32 = 1 + 3 * u32::try_from(width)?;
-    let board_depth: u32 = 2 * u32::try_from(depth)?;
-
-    let row_boards: Vec<ComponentId> = (0..height)
-        .map(|y| {
-            sandbox.add_component(
-                &CircuitBoard::new()
-                    .width(board_width)
-                    .height(board_depth)
-                    .color([51, 51, 51])
-                    .build()
-                    .position([0, y as i32 * 900, 0]),
-            )
-        })
-        .collect();
+/// Summarizes what was encoded, once everything else has been reported.
+fn report_display(injection: &Injection) {
+    if let Some(title) = &injection.title {
+        eprintln!("{}", title);
+    }
+    eprintln!(
+        "{}x{} display, {} frames, {} pixel toggles",
+        injection.width, injection.height, injection.frames, injection.toggles
+    );
+}
 
-    let mut row_frame_delayers = Vec::new();
-
-    for y in 0..height {
-        let mut frame_delayers = Vec::new();
-        for z in 0..depth {
-            // Subtract a tick from timing delayers that correspond to chunking delayers.
-            let chunk_compensation = if (z + 1) % 400 == 0 { 1 } else { 0 };
-
-            frame_delayers.push(
-                sandbox.add_component(
-                    &Delayer::new()
-                        .delay(10 - chunk_compensation)
-                        .build()
-                        .parent(Some(row_boards[y]))
-                        .position([150, 150, z as i32 * 600 + 150]),
-                ),
-            );
-        }
-        for z in 1..depth {
-            sandbox
-                .add_wire(
-                    PegAddress {
-                        component: frame_delayers[z - 1],
-                        peg_type: PegType::Output,
-                        peg_index: 0,
-                    },
-                    PegAddress {
-                        component: frame_delayers[z],
-                        peg_type: PegType::Input,
-                        peg_index: 0,
-                    },
-                    0.0,
-                )
-                .unwrap();
+/// Encodes the video and describes the circuit that would be added to a save, on standard output
+/// and optionally as JSON.
+fn report_plan(args: &PlanArgs) -> anyhow::Result<()> {
+    let options = &args.options;
+    let observer = Console;
+    let cancellation = cancel_on_interrupt()?;
+    let timing = configure_timing(options).context(Failure::Arguments)?;
+    let phases = PhaseTimes::default();
+
+    let encoded = encode_input(options, &timing, &phases, &observer, &cancellation)?;
+    if cancellation.is_cancelled() {
+        return Err(anyhow!(Failure::Cancelled));
+    }
+    encoded.circuit.validate().context(Failure::Placement)?;
+    report_audit(&encoded, &observer);
+
+    let (circuit, injection) = (&encoded.circuit, &encoded.injection);
+    let boards = encoded.layout.boards().len();
+    let estimated_size = encoded.estimated_size;
+    let stats = diagnostics::Stats {
+        width: injection.width,
+        height: injection.height,
+        frames: injection.frames,
+        toggles: injection.toggles,
+        chunk_interval: timing.chunk_interval,
+        estimated_size,
+        max_size: options.max_size_mib << 20,
+    };
+    let (delayers, pegs, sockets) = plan::component_counts(circuit);
+    let plan = Plan {
+        title: injection.title.clone(),
+        width: injection.width,
+        height: injection.height,
+        frames: injection.frames,
+        toggles: injection.toggles,
+        tick_rate: options.tick_rate,
+        frame_period: timing.frame_period(),
+        decimation: timing.decimation,
+        start_delay: options.start_delay.unwrap_or(0),
+        chunk_interval: timing.chunk_interval,
+        boards,
+        bounds: injection.bounds,
+        delayers,
+        pegs,
+        sockets,
+        wires: circuit.wires.len(),
+        estimated_size,
+        suggestions: diagnostics::suggestions(&stats),
+    };
+
+    plan.write_text(&mut stdout().lock())
+        .context(Failure::Write)?;
+    if let Some(path) = &args.json {
+        (|| -> anyhow::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            plan.write_json(&mut writer)?;
+            writer.flush()?;
+            Ok(())
+        })()
+        .with_context(|| format!("cannot write plan {:?}", path))
+        .context(Failure::Write)?;
+    }
+    phases.report();
+    Ok(())
+}
+
+/// Quantizes a sample of frames at each threshold in a range, reporting how many pixel toggles
+/// each would cost and how much of the display would be lit.
+fn sweep(args: &SweepArgs) -> anyhow::Result<()> {
+    let options = &args.options;
+    let cancellation = cancel_on_interrupt()?;
+    let timing = configure_timing(options).context(Failure::Arguments)?;
+    let phases = PhaseTimes::default();
+    let source = open_input(options, &timing)?;
+    let encoding = options.encoding();
+    let size = encode::frame_size(&encoding, &source)?;
+
+    let decoder = FrameDecoder::new(&encoding, &phases, &cancellation, &source, size.source)?;
+    let samples = args.sample.min(source.len());
+    let decoded_frames = (0..samples)
+        .map(|frame_index| decoder.decode(frame_index))
+        .collect::<anyhow::Result<Vec<Decoded>>>()?;
+    if let Some(previews) = &args.previews {
+        create_dir_all(previews).context(Failure::Write)?;
+    }
+
+    let display_width = preprocess::display_width(&encoding, size.width);
+    let pixels = display_width * size.height * samples;
+    println!("threshold  toggles  lit");
+    for threshold in (args.min..=args.max).step_by(args.step) {
+        if cancellation.is_cancelled() {
+            return Err(anyhow!(Failure::Cancelled));
         }
-        row_frame_delayers.push(frame_delayers);
-    }
-
-    let mut row_col_last_pegs = Vec::new();
-    for y in 0..height {
-        let mut col_last_pegs = Vec::new();
-        for x in 0..width {
-            col_last_pegs.push(
-                sandbox.add_component(
-                    &ChubbySocket::new()
-                        .build()
-                        .parent(Some(row_boards[y]))
-                        .position([x as i32 * 900 + 750, 150, 150])
-                        .rotation([0.0, 1.0, 0.0, 0.0]),
-                ),
-            );
+        let mut pipeline =
+            FramePipeline::new(&encoding, size.width, size.height).with_threshold(threshold);
+        let mut last_frame: Bitmap = vec![vec![options.initially_lit; display_width]; size.height];
+        let (mut toggles, mut lit) = (0, 0);
+        for (frame_index, decoded) in decoded_frames.iter().enumerate() {
+            let frame = pipeline.process(frame_index, decoded.clone());
+            for (row, last_row) in frame.iter().zip(&last_frame) {
+                toggles += row.iter().zip(last_row).filter(|(a, b)| a != b).count();
+                lit += row.iter().filter(|&&pixel| pixel).count();
+            }
+            if let Some(previews) = args
+                .previews
+                .as_ref()
+                .filter(|_| frame_index == samples / 2)
+            {
+                let path = previews.join(format!("threshold-{:03}.png", threshold));
+                preprocess::to_image(&frame)
+                    .save(&path)
+                    .with_context(|| format!("cannot write preview {:?}", path))
+                    .context(Failure::Write)?;
+            }
+            last_frame = frame;
         }
-        row_col_last_pegs.push(col_last_pegs);
+        println!(
+            "{:>9}  {:>7}  {:>3.0}%",
+            threshold,
+            toggles,
+            100.0 * lit as f64 / pixels as f64
+        );
     }
+    Ok(())
+}
 
-    let mut last_frame = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
-        width as u32,
-        height as u32,
-        Rgb([0, 0, 0]),
-    ));
+fn inject(args: &InjectArgs, matches: &ArgMatches) -> anyhow::Result<()> {
+    let options = &args.options;
+    let observer = Console;
+    let cancellation = cancel_on_interrupt()?;
+    let timing = configure_timing(options).context(Failure::Arguments)?;
+    let phases = PhaseTimes::default();
 
-    for (frame_index, path) in frame_files.iter().enumerate() {
-        eprintln!("{}", frame_index);
-        let z = (frame_index + 1) * 2;
-        let current_frame = image::open(path)?;
-        if current_frame.width() as usize != width || current_frame.height() as usize != height {
-            bail!("{:?}: frame does not match size of first frame", path);
+    // Anything that differs from the lockfile, other than the frames, is known before encoding.
+    let parameters = effective_parameters(matches);
+    let locked = match &args.lockfile {
+        Some(path) if args.locked => {
+            let lock = Lock::read(path)
+                .with_context(|| format!("cannot read lockfile {:?}", path))
+                .context(Failure::Arguments)?;
+            lock.check_parameters(&parameters)
+                .context(Failure::Arguments)?;
+            Some(lock)
         }
+        _ => None,
+    };
 
-        // Force inserting a delayer every once in a while, to "chunk" the huge nets made
-        // by pixel signal wires and effectively reduce UPS.
-        // The additional delay caused by these delayers is compensated for in the timing delayers.
-        let at_chunk_boundary = (frame_index + 1) % 200 == 0;
-        if at_chunk_boundary {
-            for y in 0..height {
-                for x in 0..width {
-                    let chunk_delayer = sandbox.add_component(
-                        &Delayer::new()
-                            .delay(1)
-                            .build()
-                            .parent(Some(row_boards[y]))
-                            .position([x as i32 * 900 + 750, 150, z as i32 * 600 - 450])
-                            .rotation([0.0, 1.0, 0.0, 0.0]),
-                    );
-                    sandbox
-                        .add_wire(
-                            PegAddress {
-                                component: chunk_delayer,
-                                peg_type: PegType::Output,
-                                peg_index: 0,
-                            },
-                            PegAddress {
-                                component: row_col_last_pegs[y][x],
-                                peg_type: PegType::Input,
-                                peg_index: 0,
-                            },
-                            0.0,
-                        )
-                        .unwrap();
+    let mut previous = None;
+    let save = match &args.path {
+        Some(path) => {
+            let path = path.as_path();
+            if is_stdio(path) {
+                if options.y4m.as_deref().is_some_and(is_stdio) {
+                    return Err(anyhow!(
+                        "the save and the frames cannot both be read from standard input"
+                    )
+                    .context(Failure::Arguments));
+                }
+            } else {
+                if args.output.is_none() && !args.in_place {
+                    return Err(anyhow!(
+                        "choose where to write the save with --output or --in-place"
+                    )
+                    .context(Failure::Arguments));
+                }
+                // A save that was injected before already contains a copy of the video, and
+                // injecting again adds a second one on top of it.
+                previous = sidecar::find_injection(path).context(Failure::SaveParse)?;
+                if let Some(previous) = &previous {
+                    observer.on_warning(&format!(
+                        "save was already injected at {} by version {} with arguments `{}`",
+                        previous.injected_at, previous.tool_version, previous.arguments,
+                    ));
                 }
             }
+
+            observer.on_phase_change(Phase::SaveIo);
+            let file = phases
+                .time(Phase::SaveIo, || read_save(path))
+                .context(Failure::SaveParse)?;
+            Some((path, file))
         }
+        None => None,
+    };
 
-        for y in 0..height {
-            let mut row_last_delayer = row_frame_delayers[y][z];
-            for x in 0..width {
-                let last_pixel = to_1bit(last_frame.get_pixel(x as u32, (height - 1 - y) as u32));
-                let current_pixel =
-                    to_1bit(current_frame.get_pixel(x as u32, (height - 1 - y) as u32));
-                if current_pixel != last_pixel {
-                    let pixel_delayer = sandbox.add_component(
-                        &Delayer::new()
-                            .delay(1)
-                            .build()
-                            .parent(Some(row_boards[y]))
-                            .position([x as i32 * 900 - 450, 150, z as i32 * 600 - 150])
-                            .rotation([0.0, 1.0, 0.0, 0.0]),
-                    );
-
-                    let pixel_peg;
-                    // Chunking delayers replace the pegs that would usually be generated:
-                    if at_chunk_boundary {
-                        pixel_peg = row_col_last_pegs[y][x];
-                    } else {
-                        pixel_peg = sandbox.add_component(
-                            &Peg::new().build().parent(Some(row_boards[y])).position([
-                                x as i32 * 900 + 750,
-                                150,
-                                z as i32 * 600 - 450,
-                            ]),
-                        );
-                    }
-
-                    sandbox
-                        .add_wire(
-                            PegAddress {
-                                component: row_last_delayer,
-                                peg_type: PegType::Input,
-                                peg_index: 0,
-                            },
-                            PegAddress {
-                                component: pixel_delayer,
-                                peg_type: PegType::Input,
-                                peg_index: 0,
-                            },
-                            0.0,
-                        )
-                        .unwrap();
-                    sandbox
-                        .add_wire(
-                            PegAddress {
-                                component: pixel_delayer,
-                                peg_type: PegType::Output,
-                                peg_index: 0,
-                            },
-                            PegAddress {
-                                component: pixel_peg,
-                                peg_type: PegType::Input,
-                                peg_index: 0,
-                            },
-                            0.0,
-                        )
-                        .unwrap();
-
-                    // This wire is not needed if using a chunking delayer
-                    if !at_chunk_boundary {
-                        sandbox
-                            .add_wire(
-                                PegAddress {
-                                    component: pixel_peg,
-                                    peg_type: PegType::Input,
-                                    peg_index: 0,
-                                },
-                                PegAddress {
-                                    component: row_col_last_pegs[y][x],
-                                    peg_type: PegType::Input,
-                                    peg_index: 0,
-                                },
-                                0.0,
-                            )
-                            .unwrap();
-                    }
-
-                    row_last_delayer = pixel_delayer;
-                    row_col_last_pegs[y][x] = pixel_peg;
-                }
-            }
+    let encoded = encode_input(options, &timing, &phases, &observer, &cancellation)?;
+    if cancellation.is_cancelled() {
+        return Err(anyhow!(Failure::Cancelled));
+    }
+
+    // Targets are only touched once the circuit is known to be complete and well-formed, and
+    // the save is only written back if every component and wire was added.
+    encoded.circuit.validate().context(Failure::Placement)?;
+    report_audit(&encoded, &observer);
+    let injection = &encoded.injection;
+    if let Some(locked) = &locked {
+        locked
+            .check_frames(&injection.frame_hashes)
+            .context(Failure::Frames)?;
+    }
+
+    // Earlier frames could in principle be kept from the previous injection, but the save
+    // cannot be edited in place yet, so this is only reported.
+    if let Some(previous) = previous
+        .as_ref()
+        .filter(|previous| !previous.injection.frame_hashes.is_empty())
+    {
+        let previous_hashes = &previous.injection.frame_hashes;
+        match sidecar::first_changed_frame(previous_hashes, &injection.frame_hashes) {
+            Some(frame) => eprintln!(
+                "frames from {} onward differ from the previous injection",
+                frame
+            ),
+            None => eprintln!("frames are identical to the previous injection"),
+        }
+    }
+
+    if let Some(schematic) = &args.schematic {
+        observer.on_phase_change(Phase::Emission);
+        phases
+            .time(Phase::Emission, || -> anyhow::Result<()> {
+                let mut target = JsonSchematic::new(BufWriter::new(File::create(schematic)?));
+                target.emit(&encoded.circuit, encoded.layout.as_ref())?;
+                target.into_inner().flush()?;
+                Ok(())
+            })
+            .context(Failure::Write)?;
+    }
+
+    if let Some((path, file)) = save {
+        // Without an output path, the save is rewritten in place, which requires --in-place, or
+        // goes to standard output if it came from standard input.
+        let path = args.output.as_deref().unwrap_or(path);
+        observer.on_phase_change(Phase::Emission);
+        let mut sandbox = Sandbox::from(&file.migrate());
+        phases
+            .time(Phase::Emission, || {
+                sandbox.emit(&encoded.circuit, encoded.layout.as_ref())
+            })
+            .context(Failure::Placement)?;
+
+        preview(&encoded, path, previous.as_ref(), &observer);
+
+        observer.on_phase_change(Phase::SaveIo);
+        let file = BlotterFile::V6((&sandbox).into());
+        if is_stdio(path) {
+            // Nothing is left next to the save to record the injection or back it up.
+            phases
+                .time(Phase::SaveIo, || write_stdout(&file))
+                .context(Failure::Write)?;
+        } else {
+            write_save(args, path, &file, injection, &phases)?;
         }
+    }
+
+    if let (Some(path), None) = (&args.lockfile, &locked) {
+        Lock::new(parameters, &injection.frame_hashes)
+            .write(path)
+            .with_context(|| format!("cannot write lockfile {:?}", path))
+            .context(Failure::Write)?;
+    }
+
+    report_display(injection);
+    phases.report();
+
+    Ok(())
+}
+
+/// Renders the effective value of every argument in `matches` that affects the generated
+/// circuit, including defaults, as sorted `name=value` pairs.
+fn effective_parameters(matches: &ArgMatches) -> Vec<String> {
+    // Where the result is written, how many threads compute it, which frames are snapshotted,
+    // what size limits it is checked against and what the video is called do not change it.
+    const UNLOCKED: [&str; 14] = [
+        "path",
+        "output",
+        "in_place",
+        "no_backup",
+        "schematic",
+        "lockfile",
+        "locked",
+        "jobs",
+        "prefetch",
+        "snapshot",
+        "snapshot_frame",
+        "max_size_mib",
+        "abort_size_mib",
+        "title",
+    ];
+    let mut parameters: Vec<String> = matches
+        .ids()
+        .map(|id| id.as_str())
+        .filter(|id| !UNLOCKED.contains(id))
+        .filter_map(|id| {
+            // Argument groups have IDs too, but no values.
+            let values = matches.try_get_raw(id).ok()??;
+            let values: Vec<_> = values.map(|value| value.to_string_lossy()).collect();
+            Some(format!("{}={}", id, values.join(",")))
+        })
+        .collect();
+    parameters.sort();
+    parameters
+}
+
+/// Whether `path` stands for standard input or output.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Reads a save from a file, or from standard input. Standard input is read into memory first,
+/// as it cannot be seeked.
+fn read_save(path: &Path) -> anyhow::Result<BlotterFile> {
+    let parse = |e| anyhow!("cannot parse blotter file: {:?}", e);
+    if is_stdio(path) {
+        let mut data = Vec::new();
+        stdin().read_to_end(&mut data)?;
+        BlotterFile::read(&mut Cursor::new(data)).map_err(parse)
+    } else {
+        let mut reader = BufReader::new(File::open(path)?);
+        BlotterFile::read(&mut reader).map_err(parse)
+    }
+}
+
+/// Serializes a save in memory, as standard output cannot be seeked, and writes it out.
+fn write_stdout(file: &BlotterFile) -> anyhow::Result<()> {
+    let mut writer = Cursor::new(Vec::new());
+    file.write(&mut writer)
+        .map_err(|e| anyhow!("cannot write blotter file: {:?}", e))?;
+    let mut stdout = stdout().lock();
+    stdout.write_all(writer.get_ref())?;
+    stdout.flush()?;
+    Ok(())
+}
 
-        last_frame = current_frame;
+/// Writes a save to a file, backing up the original first if it is overwritten, and records
+/// the injection next to it.
+fn write_save(
+    args: &InjectArgs,
+    path: &Path,
+    file: &BlotterFile,
+    injection: &Injection,
+    phases: &PhaseTimes,
+) -> anyhow::Result<()> {
+    if args.in_place && !args.no_backup {
+        let backup = phases
+            .time(Phase::SaveIo, || back_up(path))
+            .context(Failure::Write)?;
+        eprintln!("backed up original save to {:?}", backup);
     }
+    phases
+        .time(Phase::SaveIo, || {
+            write_atomically(path, |writer| {
+                file.write(writer)
+                    .map_err(|e| anyhow!("cannot write blotter file: {:?}", e))
+            })
+        })
+        .context(Failure::Write)?;
 
+    let arguments: Vec<String> = args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    sidecar::write(
+        &sidecar::sidecar_path(path),
+        &arguments.join(" "),
+        injection,
+    )
+    .context(Failure::Write)?;
     Ok(())
 }
 
-fn to_1bit(pixel: Rgba<u8>) -> bool {
-    pixel.to_luma().0[0] > 127
+/// Copies the file at `path` to `<name>.bak-<timestamp>` next to it, with the timestamp in
+/// seconds since the Unix epoch, and returns the path of the copy.
+fn back_up(path: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{:?} is not a file path", path))?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut backup_name = file_name.to_os_string();
+    backup_name.push(format!(".bak-{}", timestamp));
+    let backup = path.with_file_name(backup_name);
+    copy(path, &backup).with_context(|| format!("cannot back up save to {:?}", backup))?;
+    Ok(backup)
+}
+
+/// Writes a file through a temporary file in the same directory, which is renamed over `path`
+/// only once everything was written, so that a failed write leaves the original intact.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{:?} is not a file path", path))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        rename(&temp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = remove_file(&temp_path);
+    }
+    result
+}
+
+/// Summarizes what writing `encoded` changes in the save at `path`, so that the run can be
+/// sanity-checked against a world the user cares about.
+fn preview(encoded: &Encoded, path: &Path, previous: Option<&Record>, observer: &dyn Observer) {
+    let Bounds { min, max } = encoded.injection.bounds;
+    eprintln!(
+        "adding {} components on {} boards and {} wires to {:?}, covering {:?} to {:?}",
+        encoded.circuit.components.len(),
+        encoded.layout.boards().len(),
+        encoded.circuit.wires.len(),
+        path,
+        min,
+        max,
+    );
+    eprintln!("existing components are left unchanged");
+    if let Some(previous) = previous {
+        if previous
+            .injection
+            .bounds
+            .intersects(&encoded.injection.bounds)
+        {
+            observer.on_warning("new boards overlap the boards of the previous injection");
+        }
+    }
+}
+
+/// Name of the video, as given with --title or, for a video file, as found in its metadata.
+/// A title that cannot be read is only warned about, since nothing depends on it.
+fn title(options: &Options) -> Option<String> {
+    match (&options.title, &options.video) {
+        (Some(title), _) => Some(title.clone()),
+        (None, Some(video)) => remote::localize(video)
+            .and_then(|path| video::title(&path))
+            .unwrap_or_else(|error| {
+                eprintln!("warning: cannot read the title of the video: {:#}", error);
+                None
+            }),
+        (None, None) => None,
+    }
+}
+
+/// Warns about wiring problems in `encoded`, giving the world position of each so that it can be
+/// found in game.
+fn report_audit(encoded: &Encoded, observer: &dyn Observer) {
+    // Beyond this, the remaining problems are only counted.
+    const MAX_REPORTED: usize = 20;
+
+    let findings = audit::audit(&encoded.circuit);
+    let boards = encoded.layout.boards();
+    for finding in findings.iter().take(MAX_REPORTED) {
+        let component = &encoded.circuit.components[finding.node.0];
+        let placement = encoded.layout.place(component);
+        let origin = boards[placement.board].position;
+        let position: Vec<i32> = origin
+            .iter()
+            .zip(placement.position)
+            .map(|(origin, offset)| origin + offset)
+            .collect();
+        let problem = match finding.problem {
+            Problem::JoinedOutputs { other } => format!(
+                "output is wired to the output of {:?}",
+                encoded.circuit.components[other.0].slot
+            ),
+            Problem::FloatingInput => "input is not driven by any output".to_string(),
+            Problem::UnconnectedPeg => "peg is not wired to anything".to_string(),
+        };
+        observer.on_warning(&format!(
+            "{} at {:?} ({:?}, board {})",
+            problem, position, component.slot, placement.board
+        ));
+    }
+    if findings.len() > MAX_REPORTED {
+        observer.on_warning(&format!(
+            "{} more wiring problems",
+            findings.len() - MAX_REPORTED
+        ));
+    }
+}
+
+/// Frame rate of the source, as given with --source-fps or, for a video file, as reported by
+/// ffprobe.
+fn source_fps(options: &Options) -> anyhow::Result<Option<f64>> {
+    match (options.source_fps, &options.video) {
+        (Some(source_fps), _) => Ok(Some(source_fps)),
+        (None, Some(video)) => video::frame_rate(&remote::localize(video)?).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+fn configure_timing(options: &Options) -> anyhow::Result<Timing> {
+    let source_fps = source_fps(options)?;
+    if let (None, Some(detected)) = (options.source_fps, source_fps) {
+        eprintln!("detected a source frame rate of {:.3} fps", detected);
+    }
+    if options.target_fps.is_some() && source_fps.is_none() {
+        bail!("converting to a target frame rate requires the source frame rate");
+    }
+    let mut timing = match source_fps {
+        Some(source_fps) => {
+            let target_fps = options.target_fps.unwrap_or(source_fps);
+            if !(source_fps > 0.0 && target_fps > 0.0 && options.tick_rate > 0.0) {
+                bail!("frame and tick rates must be positive");
+            }
+            // Taking every nth frame slows the source down by the same factor, but a target
+            // frame rate is reached by resampling the frames that are left.
+            let fps = match options.target_fps {
+                Some(target_fps) => target_fps,
+                None => source_fps / options.every_nth as f64,
+            };
+            let timing = Timing::from_fps(fps, options.tick_rate);
+            eprintln!(
+                "frame period {} ticks, every {} source frame(s), effective {:.2} fps",
+                timing.frame_period(),
+                timing.decimation,
+                timing.effective_fps(options.tick_rate),
+            );
+            timing
+        }
+        None => Timing::default(),
+    };
+    if let Some(half_period) = options.half_period {
+        // Chunk compensation takes a tick off some timing delayers, which must stay above
+        // the minimum.
+        if half_period < MIN_HALF_PERIOD {
+            bail!("half period must be at least {} ticks", MIN_HALF_PERIOD);
+        }
+        timing.half_period = half_period;
+    }
+    timing.chunk_interval = options.chunk_interval;
+    if options.guard_ticks >= timing.frame_period() {
+        bail!(
+            "guard of {} ticks does not fit in the frame period of {} ticks",
+            options.guard_ticks,
+            timing.frame_period()
+        );
+    }
+    timing.guard_ticks = options.guard_ticks;
+    // The countdown frames fill the start delay instead of the timing chain waiting it out.
+    let start_delay = options.start_delay.unwrap_or(0);
+    let shortest_countdown = (preprocess::COUNTDOWN_FRAMES as u32) * 2 * MIN_HALF_PERIOD;
+    if options.countdown && start_delay < shortest_countdown {
+        bail!(
+            "a countdown needs a start delay of at least {} ticks",
+            shortest_countdown
+        );
+    }
+    timing.start_delay = if options.countdown { 0 } else { start_delay };
+    Ok(timing)
+}
+
+/// Opens the frame source selected by the options, leaving out the frames that are not shown.
+fn open_input(
+    options: &Options,
+    timing: &Timing,
+) -> anyhow::Result<Decimated<Box<dyn FrameSource>>> {
+    let source: Box<dyn FrameSource> = if let Some(text) = &options.ticker {
+        Box::new(Ticker::new(
+            text,
+            options.ticker_width as u32,
+            options.ticker_speed as u32,
+        ))
+    } else if let Some(animation) = &options.animation {
+        Box::new(frames::read_animation(animation).context(Failure::Frames)?)
+    } else if let Some(y4m) = &options.y4m {
+        Box::new(Y4m::open(y4m).context(Failure::Frames)?)
+    } else if let Some(video) = &options.video {
+        let video = remote::localize(video).context(Failure::Frames)?;
+        Box::new(VideoFile::open(&video).context(Failure::Frames)?)
+    } else if let Some(list) = &options.frame_list {
+        Box::new(frames::read_frame_list(list).context(Failure::Frames)?)
+    } else if let Some(archive) = &options.frame_archive {
+        let archive = remote::localize(archive).context(Failure::Frames)?;
+        Box::new(frames::read_archive(&archive, options.pattern.as_ref()).context(Failure::Frames)?)
+    } else {
+        // --frames also takes an archive, for the common case of keeping frames in one file,
+        // which may be downloaded. Directories can only be local.
+        let frames = remote::localize(&options.frames).context(Failure::Frames)?;
+        if frames.is_file() {
+            Box::new(
+                frames::read_archive(&frames, options.pattern.as_ref()).context(Failure::Frames)?,
+            )
+        } else {
+            Box::new(
+                frames::find_frames(&frames, options.pattern.as_ref()).context(Failure::Frames)?,
+            )
+        }
+    };
+    let source: Box<dyn FrameSource> = Box::new(Decimated::new(
+        Trimmed::new(source, options.start_frame, options.end_frame),
+        options.every_nth,
+    ));
+    let source_fps = source_fps(options).context(Failure::Frames)?;
+    let source: Box<dyn FrameSource> = match (source_fps, options.target_fps) {
+        (Some(source_fps), Some(target_fps)) => Box::new(Resampled::new(
+            source,
+            source_fps / options.every_nth as f64,
+            target_fps,
+        )),
+        _ => source,
+    };
+    if source.is_empty() {
+        return Err(anyhow!("no frames left after trimming").context(Failure::Arguments));
+    }
+    Ok(Decimated::new(source, timing.decimation))
+}
+
+/// Encodes the frames selected by the options into a circuit. Nothing is added to the save
+/// here: the result is only emitted once the whole encode has succeeded.
+fn encode_input(
+    options: &Options,
+    timing: &Timing,
+    phases: &PhaseTimes,
+    observer: &dyn Observer,
+    cancellation: &CancellationToken,
+) -> anyhow::Result<Encoded> {
+    let source = open_input(options, timing)?;
+    let encoding = encode::Options {
+        title: title(options),
+        ..options.encoding()
+    };
+    let encoded = encode::encode(&encoding, timing, &source, phases, observer, cancellation)?;
+    if encoded.junction_pegs_saved > 0 {
+        eprintln!("eliminated {} junction pegs", encoded.junction_pegs_saved);
+    }
+    eprintln!(
+        "{} components and {} wires, adding about {} MiB to the save",
+        encoded.circuit.components.len(),
+        encoded.circuit.wires.len(),
+        encoded.estimated_size >> 20,
+    );
+    Ok(encoded)
 }
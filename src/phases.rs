@@ -0,0 +1,60 @@
+//! Wall-clock accounting of where a run spends its time.
+
+use std::{
+    fs,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::progress::Phase;
+
+const PHASES: [(Phase, &str); 5] = [
+    (Phase::FrameIo, "frame I/O"),
+    (Phase::Preprocessing, "preprocessing"),
+    (Phase::Encoding, "encoding"),
+    (Phase::Emission, "emission"),
+    (Phase::SaveIo, "serialization"),
+];
+
+/// Time spent in each phase, summed over all threads. Frame I/O and preprocessing run on several
+/// threads at once, so their totals can exceed the wall-clock time of the run.
+#[derive(Default)]
+pub struct PhaseTimes {
+    nanos: [AtomicU64; PHASES.len()],
+}
+
+impl PhaseTimes {
+    pub fn add(&self, phase: Phase, elapsed: Duration) {
+        self.nanos[phase as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn time<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.add(phase, started.elapsed());
+        result
+    }
+
+    pub fn report(&self) {
+        for (phase, name) in PHASES {
+            let nanos = self.nanos[phase as usize].load(Ordering::Relaxed);
+            eprintln!("{:>16}: {:.2?}", name, Duration::from_nanos(nanos));
+        }
+        if let Some(peak) = peak_memory_kib() {
+            eprintln!("{:>16}: {} MiB", "peak memory", peak >> 10);
+        }
+    }
+}
+
+/// Peak resident memory of this process, where the platform reports it.
+fn peak_memory_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
@@ -0,0 +1,179 @@
+//! A description of the circuit a run would generate, for review before anything is emitted.
+//!
+//! The plan is written either as text for people or as JSON for scripts:
+//!
+//! ```json
+//! {
+//!   "title": "Bad Apple!!",
+//!   "display": {"width": 48, "height": 36, "frames": 6572, "toggles": 412345},
+//!   "timing": {"tick_rate": 200, "frame_period": 20, "decimation": 1, "start_delay": 0,
+//!              "chunk_interval": 200},
+//!   "layout": {"boards": 36, "bounds": {"min": [0, 0, 0], "max": [14400, 21600, 3943800]}},
+//!   "circuit": {"delayers": 900000, "pegs": 412345, "sockets": 1728, "wires": 1500000,
+//!               "estimated_size": 123456789},
+//!   "suggestions": []
+//! }
+//! ```
+
+use std::io::Write;
+
+use crate::{
+    circuit::{Circuit, Kind},
+    layout::Bounds,
+};
+
+/// The structure of a display driver, as it would be generated.
+pub struct Plan {
+    /// Name of the video, if it has one.
+    pub title: Option<String>,
+    /// Number of display columns, including any extra planes and sync column.
+    pub width: usize,
+    pub height: usize,
+    pub frames: usize,
+    pub toggles: usize,
+    /// Simulation rate the timing is tuned for, in ticks per second.
+    pub tick_rate: f64,
+    /// Uniform frame period, in ticks.
+    pub frame_period: u32,
+    /// Only every `decimation`-th source frame is displayed.
+    pub decimation: usize,
+    /// Ticks between the start pulse and the first frame, beyond the usual.
+    pub start_delay: u32,
+    /// Number of frames between chunking delayers.
+    pub chunk_interval: usize,
+    pub boards: usize,
+    pub bounds: Bounds,
+    pub delayers: usize,
+    pub pegs: usize,
+    pub sockets: usize,
+    pub wires: usize,
+    /// Estimated size added to the save, in bytes.
+    pub estimated_size: u64,
+    pub suggestions: Vec<String>,
+}
+
+/// Counts the delayers, pegs and sockets of `circuit`.
+pub fn component_counts(circuit: &Circuit) -> (usize, usize, usize) {
+    circuit.components.iter().fold(
+        (0, 0, 0),
+        |(delayers, pegs, sockets), component| match component.kind {
+            Kind::Delayer { .. } => (delayers + 1, pegs, sockets),
+            Kind::Peg => (delayers, pegs + 1, sockets),
+            Kind::Socket => (delayers, pegs, sockets + 1),
+        },
+    )
+}
+
+impl Plan {
+    pub fn write_text(&self, w: &mut impl Write) -> anyhow::Result<()> {
+        if let Some(title) = &self.title {
+            writeln!(w, "title        {}", title)?;
+        }
+        writeln!(
+            w,
+            "display      {}x{}, {} frames, {} pixel toggles",
+            self.width, self.height, self.frames, self.toggles
+        )?;
+        writeln!(
+            w,
+            "timing       {}-tick frames at {} ticks/s ({:.2} fps), every {} source frame(s)",
+            self.frame_period,
+            self.tick_rate,
+            self.tick_rate / f64::from(self.frame_period),
+            self.decimation,
+        )?;
+        if self.start_delay > 0 {
+            writeln!(w, "start delay  {} ticks", self.start_delay)?;
+        }
+        writeln!(
+            w,
+            "chunking     a delayer in every column every {} frames",
+            self.chunk_interval
+        )?;
+        writeln!(
+            w,
+            "layout       {} boards, spanning {:?} to {:?}",
+            self.boards, self.bounds.min, self.bounds.max
+        )?;
+        writeln!(
+            w,
+            "circuit      {} delayers, {} pegs, {} sockets, {} wires",
+            self.delayers, self.pegs, self.sockets, self.wires
+        )?;
+        writeln!(
+            w,
+            "size         about {} MiB added to the save",
+            self.estimated_size >> 20
+        )?;
+        for suggestion in &self.suggestions {
+            writeln!(w, "suggestion   {}", suggestion)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, w: &mut impl Write) -> anyhow::Result<()> {
+        writeln!(w, "{{")?;
+        let title = self
+            .title
+            .as_deref()
+            .map_or_else(|| "null".to_string(), string);
+        writeln!(w, "  \"title\": {},", title)?;
+        writeln!(
+            w,
+            "  \"display\": {{\"width\": {}, \"height\": {}, \"frames\": {}, \"toggles\": {}}},",
+            self.width, self.height, self.frames, self.toggles
+        )?;
+        writeln!(
+            w,
+            "  \"timing\": {{\"tick_rate\": {}, \"frame_period\": {}, \"decimation\": {}, \
+             \"start_delay\": {}, \"chunk_interval\": {}}},",
+            self.tick_rate,
+            self.frame_period,
+            self.decimation,
+            self.start_delay,
+            self.chunk_interval
+        )?;
+        writeln!(
+            w,
+            "  \"layout\": {{\"boards\": {}, \"bounds\": {{\"min\": {}, \"max\": {}}}}},",
+            self.boards,
+            array(&self.bounds.min),
+            array(&self.bounds.max)
+        )?;
+        writeln!(
+            w,
+            "  \"circuit\": {{\"delayers\": {}, \"pegs\": {}, \"sockets\": {}, \"wires\": {}, \
+             \"estimated_size\": {}}},",
+            self.delayers, self.pegs, self.sockets, self.wires, self.estimated_size
+        )?;
+        let suggestions: Vec<String> = self
+            .suggestions
+            .iter()
+            .map(String::as_str)
+            .map(string)
+            .collect();
+        writeln!(w, "  \"suggestions\": [{}]", suggestions.join(", "))?;
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+fn array<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(T::to_string).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Quotes a string for JSON.
+fn string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
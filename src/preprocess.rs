@@ -0,0 +1,1291 @@
+//! Frame decoding and preprocessing, from source images to the 1-bit bitmaps that are encoded.
+
+use std::{cmp::Reverse, str::FromStr, sync::OnceLock, time::Instant};
+
+use anyhow::{anyhow, bail, Context};
+use image::{
+    imageops::{self, FilterType},
+    GenericImageView, GrayImage, Luma,
+};
+
+use crate::{
+    cancel::CancellationToken, encode::Options, failure::Failure, phases::PhaseTimes,
+    progress::Phase, source::FrameSource, ticker,
+};
+
+/// Corner of the source frame that is wired to the first pixel of the first row board.
+///
+/// Row boards are numbered from the bottom of the stack, and pixels from the left of the board,
+/// so the default matches a display viewed from the front.
+#[derive(Clone, Copy)]
+pub enum Origin {
+    BottomLeft,
+    BottomRight,
+    TopLeft,
+    TopRight,
+}
+
+impl FromStr for Origin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom-right" => Ok(Self::BottomRight),
+            "top-left" => Ok(Self::TopLeft),
+            "top-right" => Ok(Self::TopRight),
+            _ => bail!("unknown origin {:?}", s),
+        }
+    }
+}
+
+/// Clockwise rotation applied to each frame before it is laid out.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rotation {
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    /// Whether the rotation swaps the width and height of the frame.
+    pub fn is_quarter_turn(self) -> bool {
+        matches!(self, Self::Cw90 | Self::Cw270)
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::None),
+            "90" => Ok(Self::Cw90),
+            "180" => Ok(Self::Cw180),
+            "270" => Ok(Self::Cw270),
+            _ => bail!("rotation must be 0, 90, 180 or 270 degrees, got {:?}", s),
+        }
+    }
+}
+
+/// A rectangle in source pixel coordinates, written as `x,y,width,height`.
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn lerp(self, other: Self, t: f64) -> Self {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Self {
+            x: lerp(self.x, other.x),
+            y: lerp(self.y, other.y),
+            width: lerp(self.width, other.width),
+            height: lerp(self.height, other.height),
+        }
+    }
+
+    /// The pixels covered, as `x, y, width, height`. Edges are rounded to whole pixels, rather
+    /// than the position and size separately, so that a rectangle within the frame stays within
+    /// it. Expects a rectangle with a non-negative position.
+    pub fn pixels(self) -> (u32, u32, u32, u32) {
+        let (left, top) = (self.x.round() as u32, self.y.round() as u32);
+        let right = (self.x + self.width).round() as u32;
+        let bottom = (self.y + self.height).round() as u32;
+        (
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        )
+    }
+
+    pub fn fits_within(self, (width, height): (u32, u32)) -> bool {
+        if self.x < 0.0 || self.y < 0.0 {
+            return false;
+        }
+        let (x, y, pixels_wide, pixels_high) = self.pixels();
+        pixels_wide >= 1
+            && pixels_high >= 1
+            && x + pixels_wide <= width
+            && y + pixels_high <= height
+    }
+
+    pub fn crop(self, image: &GrayImage) -> GrayImage {
+        let (x, y, width, height) = self.pixels();
+        imageops::crop_imm(image, x, y, width, height).to_image()
+    }
+}
+
+impl FromStr for Rect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<f64>, _>>()?;
+        match parts[..] {
+            [x, y, width, height] => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => bail!("expected `x,y,width,height`, got {:?}", s),
+        }
+    }
+}
+
+/// An animated crop, interpolated from one rectangle on the first frame to another on the
+/// last frame, written as `from:to`.
+#[derive(Clone, Copy)]
+pub struct PanZoom {
+    pub from: Rect,
+    pub to: Rect,
+}
+
+impl PanZoom {
+    pub fn crop(&self, image: &GrayImage, frame_index: usize, frame_count: usize) -> GrayImage {
+        let t = if frame_count > 1 {
+            frame_index as f64 / (frame_count - 1) as f64
+        } else {
+            0.0
+        };
+        self.from.lerp(self.to, t).crop(image)
+    }
+}
+
+impl FromStr for PanZoom {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `from:to`, got {:?}", s))?;
+        Ok(Self {
+            from: from.parse()?,
+            to: to.parse()?,
+        })
+    }
+}
+
+/// Image dimensions, written as `WIDTHxHEIGHT`.
+#[derive(Clone, Copy)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| anyhow!("expected `WIDTHxHEIGHT`, got {:?}", s))?;
+        Ok(Self {
+            width: width.parse()?,
+            height: height.parse()?,
+        })
+    }
+}
+
+/// A display aspect ratio, written as `WIDTH:HEIGHT`.
+#[derive(Clone, Copy)]
+pub struct Aspect {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Aspect {
+    /// Size of frames of `width` by `height` pixels once padded out to this aspect ratio.
+    pub fn padded(self, (width, height): (u32, u32)) -> (u32, u32) {
+        let (frame_width, frame_height) = (u64::from(width), u64::from(height));
+        let (aspect_width, aspect_height) = (u64::from(self.width), u64::from(self.height));
+        if frame_width * aspect_height >= frame_height * aspect_width {
+            let padded = (frame_width * aspect_height + aspect_width / 2) / aspect_width;
+            (width, padded as u32)
+        } else {
+            let padded = (frame_height * aspect_width + aspect_height / 2) / aspect_height;
+            (padded as u32, height)
+        }
+    }
+}
+
+impl FromStr for Aspect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `WIDTH:HEIGHT`, got {:?}", s))?;
+        let aspect = Self {
+            width: width.parse()?,
+            height: height.parse()?,
+        };
+        if aspect.width == 0 || aspect.height == 0 {
+            bail!("aspect ratio cannot be zero, got {:?}", s);
+        }
+        Ok(aspect)
+    }
+}
+
+/// Filter used to resize frames to the display size.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Lanczos,
+}
+
+impl Filter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            Self::Nearest => FilterType::Nearest,
+            Self::Bilinear => FilterType::Triangle,
+            Self::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            "lanczos" => Ok(Self::Lanczos),
+            _ => bail!("unknown filter {:?}", s),
+        }
+    }
+}
+
+/// Size of source frames of `width` by `height` pixels after cropping, rotation and
+/// letterboxing, before any resizing.
+pub fn framed_dimensions(options: &Options, (width, height): (u32, u32)) -> (u32, u32) {
+    let (width, height) = match options.crop {
+        Some(rect) => {
+            let (_, _, width, height) = rect.pixels();
+            (width, height)
+        }
+        None => (width, height),
+    };
+    let rotated = if options.rotate.is_quarter_turn() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    match options.letterbox {
+        Some(aspect) => aspect.padded(rotated),
+        None => rotated,
+    }
+}
+
+/// Centers `image` on a dark frame of `width` by `height` pixels.
+fn letterbox(image: &GrayImage, (width, height): (u32, u32)) -> GrayImage {
+    let mut padded = GrayImage::new(width, height);
+    imageops::replace(
+        &mut padded,
+        image,
+        (width - image.width()) / 2,
+        (height - image.height()) / 2,
+    );
+    padded
+}
+
+/// Size that frames of `width` by `height` pixels, after [framing](framed_dimensions), are
+/// resized to by `--width`, `--height` or `--scale`, if any. Giving only a width or height keeps
+/// the aspect ratio.
+pub fn resized_dimensions(options: &Options, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+    let scaled = |length: u32, scale: f64| ((f64::from(length) * scale).round() as u32).max(1);
+    match (options.width, options.height, options.scale) {
+        (Some(new_width), Some(new_height), _) => Some((new_width, new_height)),
+        (Some(new_width), None, _) => Some((
+            new_width,
+            scaled(height, f64::from(new_width) / f64::from(width)),
+        )),
+        (None, Some(new_height), _) => Some((
+            scaled(width, f64::from(new_height) / f64::from(height)),
+            new_height,
+        )),
+        (None, None, Some(scale)) => Some((scaled(width, scale), scaled(height, scale))),
+        (None, None, None) => None,
+    }
+}
+
+/// Stretches the luma range of the source so that low-contrast footage uses the full range
+/// before thresholding.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ContrastStretch {
+    Off,
+    /// Stretch each frame by its own luma range.
+    Frame,
+    /// Stretch every frame by the luma range of the whole video.
+    Global,
+}
+
+impl FromStr for ContrastStretch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "frame" => Ok(Self::Frame),
+            "global" => Ok(Self::Global),
+            _ => bail!("unknown contrast stretch mode {:?}", s),
+        }
+    }
+}
+
+/// What transparent parts of frames show.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaBackground {
+    Black,
+    White,
+    /// Whatever the display showed on the previous frame.
+    Keep,
+}
+
+impl FromStr for AlphaBackground {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "black" => Ok(Self::Black),
+            "white" => Ok(Self::White),
+            "keep" => Ok(Self::Keep),
+            _ => bail!("unknown alpha background {:?}", s),
+        }
+    }
+}
+
+/// What of each frame is drawn on the display.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Bright areas are lit.
+    Fill,
+    /// Only the edges between areas are lit.
+    Outline,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" => Ok(Self::Fill),
+            "outline" => Ok(Self::Outline),
+            _ => bail!("unknown mode {:?}", s),
+        }
+    }
+}
+
+/// Replaces each pixel with the magnitude of the luma gradient around it, by the Sobel operator,
+/// so that only edges stay bright. Pixels past the border repeat the nearest edge pixel.
+fn sobel(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let luma = |x: i64, y: i64| {
+        let x = x.clamp(0, i64::from(width) - 1) as u32;
+        let y = y.clamp(0, i64::from(height) - 1) as u32;
+        f32::from(image.get_pixel(x, y).0[0])
+    };
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x, y) = (i64::from(x), i64::from(y));
+        let gradient_x = luma(x + 1, y - 1) + 2.0 * luma(x + 1, y) + luma(x + 1, y + 1)
+            - luma(x - 1, y - 1)
+            - 2.0 * luma(x - 1, y)
+            - luma(x - 1, y + 1);
+        let gradient_y = luma(x - 1, y + 1) + 2.0 * luma(x, y + 1) + luma(x + 1, y + 1)
+            - luma(x - 1, y - 1)
+            - 2.0 * luma(x, y - 1)
+            - luma(x + 1, y - 1);
+        // A hard edge from black to white has a gradient of 4 * 255.
+        let magnitude = (gradient_x * gradient_x + gradient_y * gradient_y).sqrt() / 4.0;
+        Luma([magnitude.min(255.0) as u8])
+    })
+}
+
+/// How shades of gray are reduced to lit and dark pixels.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+    /// Each pixel is compared against the threshold on its own.
+    None,
+    /// The error of thresholding each pixel is diffused onto its neighbours.
+    FloydSteinberg,
+    /// The threshold is offset by a tiled 8x8 Bayer matrix.
+    Bayer,
+    /// The threshold is offset by a tiled blue-noise mask.
+    BlueNoise,
+}
+
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "floyd-steinberg" => Ok(Self::FloydSteinberg),
+            "bayer" => Ok(Self::Bayer),
+            "blue-noise" => Ok(Self::BlueNoise),
+            _ => bail!("unknown dithering mode {:?}", s),
+        }
+    }
+}
+
+/// Leaves every pixel of `image` either fully dark or fully lit, dithered around `threshold`.
+fn dither(image: &mut GrayImage, mode: Dither, threshold: u8) {
+    match mode {
+        Dither::None => {}
+        Dither::FloydSteinberg => floyd_steinberg(image, threshold),
+        Dither::Bayer => ordered_dither(image, threshold, BAYER_SIZE, bayer_rank),
+        Dither::BlueNoise => {
+            let mask = blue_noise();
+            ordered_dither(image, threshold, BLUE_NOISE_SIZE, |x, y| {
+                mask[(y * BLUE_NOISE_SIZE + x) as usize]
+            })
+        }
+    }
+}
+
+/// Width and height of the Bayer matrix.
+const BAYER_SIZE: u32 = 8;
+
+/// Width and height of the blue-noise mask.
+const BLUE_NOISE_SIZE: u32 = 32;
+
+/// Lights pixels against a threshold offset by the rank of their position in a mask of `size`
+/// by `size` cells, tiled over the image. A flat shade lights the same pixels of every tile on
+/// every frame, which keeps the pattern from flickering between frames.
+fn ordered_dither(image: &mut GrayImage, threshold: u8, size: u32, rank: impl Fn(u32, u32) -> u32) {
+    let cells = (size * size) as f32;
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let offset = (rank(x % size, y % size) as f32 + 0.5) / cells - 0.5;
+        let lit = f32::from(pixel.0[0]) >= f32::from(threshold) + offset * 256.0;
+        pixel.0[0] = if lit { u8::MAX } else { 0 };
+    }
+}
+
+/// Rank of a cell of the Bayer matrix. Each bit of the coordinates picks a cell of the 2x2
+/// matrix, and the lowest bits are the most significant.
+fn bayer_rank(x: u32, y: u32) -> u32 {
+    const BASE: [[u32; 2]; 2] = [[0, 2], [3, 1]];
+    (0..BAYER_SIZE.trailing_zeros()).fold(0, |rank, bit| {
+        4 * rank + BASE[((y >> bit) & 1) as usize][((x >> bit) & 1) as usize]
+    })
+}
+
+/// Ranks of the cells of a blue-noise mask, generated on first use by the void-and-cluster
+/// method. Cells are ranked by adding lit cells one at a time into the largest gap between the
+/// others, so every threshold of the mask spreads its lit cells evenly.
+fn blue_noise() -> &'static [u32] {
+    static MASK: OnceLock<Vec<u32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE as usize;
+        let cells = size * size;
+        // How much a lit cell crowds each cell around it, by offset, wrapping around the edges.
+        const SIGMA: f32 = 1.5;
+        let kernel: Vec<f32> = (0..cells)
+            .map(|i| {
+                let wrapped = |d: usize| d.min(size - d) as f32;
+                let (dx, dy) = (wrapped(i % size), wrapped(i / size));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        let toggle = |pattern: &mut [bool], energy: &mut [f32], cell: usize| {
+            pattern[cell] = !pattern[cell];
+            let sign = if pattern[cell] { 1.0 } else { -1.0 };
+            let (cell_x, cell_y) = (cell % size, cell / size);
+            for (i, energy) in energy.iter_mut().enumerate() {
+                let dx = (i % size + size - cell_x) % size;
+                let dy = (i / size + size - cell_y) % size;
+                *energy += sign * kernel[dy * size + dx];
+            }
+        };
+        let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+            (0..cells)
+                .filter(|&i| pattern[i])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .expect("pattern has lit cells")
+        };
+        let largest_void = |pattern: &[bool], energy: &[f32]| {
+            (0..cells)
+                .filter(|&i| !pattern[i])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .expect("pattern has dark cells")
+        };
+
+        // Light a tenth of the cells at random, then move lit cells out of clusters into voids
+        // until the pattern is even.
+        let (mut pattern, mut energy) = (vec![false; cells], vec![0.0; cells]);
+        let mut state = 0x2545_f491_u32;
+        let mut lit = 0;
+        while lit < cells / 10 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let cell = state as usize % cells;
+            if !pattern[cell] {
+                toggle(&mut pattern, &mut energy, cell);
+                lit += 1;
+            }
+        }
+        for _ in 0..cells {
+            let cluster = tightest_cluster(&pattern, &energy);
+            toggle(&mut pattern, &mut energy, cluster);
+            let void = largest_void(&pattern, &energy);
+            toggle(&mut pattern, &mut energy, void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // The initial cells are ranked by taking them away from the tightest clusters first, and
+        // the rest by filling the largest voids.
+        let mut ranks = vec![0; cells];
+        let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+        for rank in (0..lit).rev() {
+            let cluster = tightest_cluster(&removed, &removed_energy);
+            toggle(&mut removed, &mut removed_energy, cluster);
+            ranks[cluster] = rank as u32;
+        }
+        for rank in lit..cells {
+            let void = largest_void(&pattern, &energy);
+            toggle(&mut pattern, &mut energy, void);
+            ranks[void] = rank as u32;
+        }
+        ranks
+    })
+}
+
+/// Thresholds each pixel in turn, from the top left, and spreads the difference between its
+/// luma and the result over the neighbours that have yet to be thresholded.
+fn floyd_steinberg(image: &mut GrayImage, threshold: u8) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut values: Vec<f32> = image.pixels().map(|pixel| f32::from(pixel.0[0])).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let lit = values[i] >= f32::from(threshold);
+            let output = if lit { u8::MAX } else { 0 };
+            let error = values[i] - f32::from(output);
+            image.put_pixel(x as u32, y as u32, Luma([output]));
+
+            if x + 1 < width {
+                values[i + 1] += error * 7.0 / 16.0;
+            }
+            if y + 1 < height {
+                if x > 0 {
+                    values[i + width - 1] += error * 3.0 / 16.0;
+                }
+                values[i + width] += error * 5.0 / 16.0;
+                if x + 1 < width {
+                    values[i + width + 1] += error / 16.0;
+                }
+            }
+        }
+    }
+}
+
+/// Luma at and above which a pixel is lit.
+pub const DEFAULT_THRESHOLD: u8 = 128;
+
+/// Threshold that splits the luma histogram of `image` into the two classes with the greatest
+/// variance between them, by Otsu's method, or `None` if every pixel has the same luma.
+fn otsu_threshold(image: &GrayImage) -> Option<u8> {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[usize::from(pixel.0[0])] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let luma_sum: u64 = (0..)
+        .zip(&histogram)
+        .map(|(luma, &count)| luma * count)
+        .sum();
+
+    let (mut dark_count, mut dark_sum) = (0, 0);
+    let mut best: Option<(f64, u8)> = None;
+    for threshold in 1..=u8::MAX {
+        // Pixels below the threshold stay dark.
+        let luma = threshold - 1;
+        dark_count += histogram[usize::from(luma)];
+        dark_sum += u64::from(luma) * histogram[usize::from(luma)];
+        let bright_count = total - dark_count;
+        if dark_count == 0 || bright_count == 0 {
+            continue;
+        }
+        let dark_mean = dark_sum as f64 / dark_count as f64;
+        let bright_mean = (luma_sum - dark_sum) as f64 / bright_count as f64;
+        let variance = dark_count as f64 * bright_count as f64 * (bright_mean - dark_mean).powi(2);
+        if best.is_none_or(|(best_variance, _)| variance > best_variance) {
+            best = Some((variance, threshold));
+        }
+    }
+    best.map(|(_, threshold)| threshold)
+}
+
+/// A quantized frame, indexed as `[y][x]` with `y = 0` being the bottom row of the display.
+pub type Bitmap = Vec<Vec<bool>>;
+
+/// Number of display columns each column of the frame is shown on: two with --duotone, for
+/// the dark and bright planes, and one otherwise.
+pub fn planes(options: &Options) -> usize {
+    1 + usize::from(options.duotone.is_some())
+}
+
+/// Number of display columns for frames `width` pixels wide, including any sync column.
+pub fn display_width(options: &Options, width: usize) -> usize {
+    width * planes(options) + usize::from(options.sync_column)
+}
+
+/// Thresholds a frame into a bitmap. With a duotone `split`, each pixel becomes two adjacent
+/// columns: the first is lit from `threshold` up to `split`, the second from `split` up.
+fn quantize(image: &GrayImage, origin: Origin, threshold: u8, split: Option<u8>) -> Bitmap {
+    let (width, height) = image.dimensions();
+    let planes = 1 + usize::from(split.is_some());
+    let (flip_x, flip_y) = match origin {
+        Origin::BottomLeft => (false, true),
+        Origin::BottomRight => (true, true),
+        Origin::TopLeft => (false, false),
+        Origin::TopRight => (true, false),
+    };
+    (0..height)
+        .map(|y| {
+            let source_y = if flip_y { height - 1 - y } else { y };
+            (0..width)
+                .flat_map(|x| {
+                    let source_x = if flip_x { width - 1 - x } else { x };
+                    let luma = image.get_pixel(source_x, source_y).0[0];
+                    let (dark, bright) = match split {
+                        Some(split) => (luma >= threshold && luma < split, luma >= split),
+                        None => (luma >= threshold, false),
+                    };
+                    [dark, bright].into_iter().take(planes)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Renders a quantized frame the way it appears on the display, lit pixels white.
+pub fn to_image(bitmap: &Bitmap) -> GrayImage {
+    let height = bitmap.len() as u32;
+    let width = bitmap.first().map_or(0, Vec::len) as u32;
+    GrayImage::from_fn(width, height, |x, y| {
+        let lit = bitmap[(height - 1 - y) as usize][x as usize];
+        Luma([if lit { 255 } else { 0 }])
+    })
+}
+
+/// Averages two frames, softening fast motion before it is quantized.
+fn blend(a: &GrayImage, b: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(a.width(), a.height(), |x, y| {
+        let sum = u16::from(a.get_pixel(x, y).0[0]) + u16::from(b.get_pixel(x, y).0[0]);
+        Luma([((sum + 1) / 2) as u8])
+    })
+}
+
+/// Returns the darkest and brightest luma values in the image.
+fn luma_range(image: &GrayImage) -> (u8, u8) {
+    image
+        .pixels()
+        .fold((u8::MAX, u8::MIN), |(low, high), pixel| {
+            (low.min(pixel.0[0]), high.max(pixel.0[0]))
+        })
+}
+
+/// Linearly maps the luma range `low..=high` onto `0..=255`.
+fn stretch_contrast(image: &mut GrayImage, (low, high): (u8, u8)) {
+    if high <= low {
+        return;
+    }
+    for pixel in image.pixels_mut() {
+        let value = pixel.0[0].clamp(low, high);
+        pixel.0[0] = (u32::from(value - low) * 255 / u32::from(high - low)) as u8;
+    }
+}
+
+/// Keeps pixels lit for a number of frames after they turn off in the source, emulating the
+/// afterglow of a CRT phosphor.
+struct PhosphorDecay {
+    frames: usize,
+    /// How many frames each pixel has been off in the source, saturating at `frames + 1`.
+    off_for: Vec<Vec<usize>>,
+}
+
+impl PhosphorDecay {
+    fn new(frames: usize, width: usize, height: usize) -> Self {
+        Self {
+            frames,
+            off_for: vec![vec![frames + 1; width]; height],
+        }
+    }
+
+    fn apply(&mut self, frame: &mut Bitmap) {
+        for (row, off_row) in frame.iter_mut().zip(&mut self.off_for) {
+            for (pixel, off_for) in row.iter_mut().zip(off_row) {
+                if *pixel {
+                    *off_for = 0;
+                } else {
+                    *off_for = (*off_for + 1).min(self.frames + 1);
+                    *pixel = *off_for <= self.frames;
+                }
+            }
+        }
+    }
+}
+
+/// Suppresses pixels that flip for a single frame, such as dithering or compression noise.
+///
+/// A pixel only follows the source once its new value has held for two frames, and until then
+/// keeps the value it was last shown with. Real changes are delayed by a frame, but a pixel
+/// flickering on every other frame is never shown at all.
+struct Denoise {
+    /// The frame last shown, starting from the display's initial state.
+    shown: Bitmap,
+    /// The previous source frame, starting from the display's initial state.
+    previous: Bitmap,
+}
+
+impl Denoise {
+    fn new(initially_lit: bool, width: usize, height: usize) -> Self {
+        Self {
+            shown: vec![vec![initially_lit; width]; height],
+            previous: vec![vec![initially_lit; width]; height],
+        }
+    }
+
+    fn apply(&mut self, frame: &mut Bitmap) {
+        let source = frame.clone();
+        for ((row, shown_row), previous_row) in
+            frame.iter_mut().zip(&self.shown).zip(&self.previous)
+        {
+            for ((pixel, &shown), &previous) in row.iter_mut().zip(shown_row).zip(previous_row) {
+                if *pixel != previous {
+                    *pixel = shown;
+                }
+            }
+        }
+        self.shown = frame.clone();
+        self.previous = source;
+    }
+}
+
+/// Limits how many pixels change from one frame to the next, deferring the others to later
+/// frames, so that scene cuts do not update the whole display in one tick.
+///
+/// Changes with the most changing neighbours are kept, so that coherent shapes appear first and
+/// isolated pixels catch up afterwards.
+struct ChangeBudget {
+    max_changes: usize,
+    /// The frame last shown, starting from the display's initial state.
+    shown: Bitmap,
+}
+
+impl ChangeBudget {
+    fn new(max_changes: usize, initially_lit: bool, width: usize, height: usize) -> Self {
+        Self {
+            max_changes,
+            shown: vec![vec![initially_lit; width]; height],
+        }
+    }
+
+    fn apply(&mut self, frame: &mut Bitmap) {
+        let changed: Vec<Vec<bool>> = frame
+            .iter()
+            .zip(&self.shown)
+            .map(|(row, shown_row)| row.iter().zip(shown_row).map(|(a, b)| a != b).collect())
+            .collect();
+        let mut changes: Vec<(usize, usize)> = changed
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|&(_, &changed)| changed)
+                    .map(move |(x, _)| (y, x))
+            })
+            .collect();
+        if changes.len() > self.max_changes {
+            let neighbours = |(y, x): (usize, usize)| {
+                let rows = y.saturating_sub(1)..(y + 2).min(changed.len());
+                rows.flat_map(|ny| {
+                    let columns = x.saturating_sub(1)..(x + 2).min(changed[ny].len());
+                    columns
+                        .filter(move |&nx| (ny, nx) != (y, x))
+                        .map(move |nx| (ny, nx))
+                })
+                .filter(|&(ny, nx)| changed[ny][nx])
+                .count()
+            };
+            changes.sort_by_cached_key(|&change| Reverse(neighbours(change)));
+            for &(y, x) in &changes[self.max_changes..] {
+                frame[y][x] = self.shown[y][x];
+            }
+        }
+        self.shown.clone_from(frame);
+    }
+}
+
+/// Appends a column that is lit on even frames and dark on odd frames, so a desynced display
+/// is immediately visible.
+fn add_sync_column(frame: &mut Bitmap, frame_index: usize) {
+    for row in frame {
+        row.push(frame_index % 2 == 0);
+    }
+}
+
+/// Generates a test sequence for a display of `width` by `height` pixels: all pixels on, all
+/// off, then each row and each column lit on its own, from the first row and column.
+pub fn self_test(width: usize, height: usize) -> Vec<Bitmap> {
+    let mut frames = vec![
+        vec![vec![true; width]; height],
+        vec![vec![false; width]; height],
+    ];
+    frames.extend((0..height).map(|lit| {
+        (0..height)
+            .map(|y| vec![y == lit; width])
+            .collect::<Bitmap>()
+    }));
+    frames.extend((0..width).map(|lit| {
+        (0..height)
+            .map(|_| (0..width).map(|x| x == lit).collect())
+            .collect::<Bitmap>()
+    }));
+    frames
+}
+
+/// Fraction of the pixels of `current` that differ from `previous`.
+pub fn changed_fraction(previous: &Bitmap, current: &Bitmap) -> f64 {
+    let (changed, total) = previous
+        .iter()
+        .flatten()
+        .zip(current.iter().flatten())
+        .fold((0, 0), |(changed, total), (a, b)| {
+            (changed + usize::from(a != b), total + 1)
+        });
+    if total == 0 {
+        0.0
+    } else {
+        changed as f64 / total as f64
+    }
+}
+
+/// Number of frames in the countdown, counting down to 1.
+pub const COUNTDOWN_FRAMES: usize = 3;
+
+/// Generates a countdown for a display of `width` by `height` pixels, showing each digit
+/// centered, or `None` if the digits do not fit on the display.
+pub fn countdown(width: usize, height: usize, origin: Origin) -> Option<Vec<Bitmap>> {
+    (1..=COUNTDOWN_FRAMES)
+        .rev()
+        .map(|digit| {
+            let digit = ticker::render(&digit.to_string());
+            // The blank column after the glyph may be cut off.
+            let (width, height) = (width as u32, height as u32);
+            if digit.width() - 1 > width || digit.height() > height {
+                return None;
+            }
+            let mut image = GrayImage::new(width, height);
+            let left = (width - (digit.width() - 1)) / 2;
+            imageops::replace(&mut image, &digit, left, (height - digit.height()) / 2);
+            Some(quantize(&image, origin, DEFAULT_THRESHOLD, None))
+        })
+        .collect()
+}
+
+/// A frame that has been through the per-frame preprocessing stages.
+#[derive(Clone)]
+pub struct Decoded {
+    pub luma: GrayImage,
+    /// With `--alpha-background keep`, the opacity of each pixel of frames that have an alpha
+    /// channel.
+    pub alpha: Option<GrayImage>,
+}
+
+/// The per-frame preprocessing stages, which only depend on the frame being processed and can
+/// therefore run on several frames in parallel.
+///
+/// Decoding fails with [`Failure::Cancelled`] once the cancellation token is cancelled, so that
+/// threads decoding ahead stop too, and with [`Failure::Frames`] if a frame cannot be read.
+pub struct FrameDecoder<'a> {
+    options: &'a Options,
+    phases: &'a PhaseTimes,
+    cancellation: &'a CancellationToken,
+    source: &'a dyn FrameSource,
+    source_dimensions: (u32, u32),
+    resize: Option<(u32, u32)>,
+    global_luma_range: Option<(u8, u8)>,
+}
+
+impl<'a> FrameDecoder<'a> {
+    pub fn new(
+        options: &'a Options,
+        phases: &'a PhaseTimes,
+        cancellation: &'a CancellationToken,
+        source: &'a dyn FrameSource,
+        source_dimensions: (u32, u32),
+    ) -> anyhow::Result<Self> {
+        // Reading every frame up front can take as long as the encode itself.
+        let global_luma_range = if options.contrast_stretch == ContrastStretch::Global {
+            let mut range = (u8::MAX, u8::MIN);
+            for frame_index in 0..source.len() {
+                if cancellation.is_cancelled() {
+                    return Err(anyhow!(Failure::Cancelled));
+                }
+                let mut luma = source
+                    .frame(frame_index)
+                    .context(Failure::Frames)?
+                    .to_luma8();
+                if let Some(rect) = options.crop {
+                    luma = rect.crop(&luma);
+                }
+                let (low, high) = luma_range(&luma);
+                range = (range.0.min(low), range.1.max(high));
+            }
+            Some(range)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            options,
+            phases,
+            cancellation,
+            source,
+            source_dimensions,
+            resize: resized_dimensions(options, framed_dimensions(options, source_dimensions)),
+            global_luma_range,
+        })
+    }
+
+    /// Decodes a frame and applies the geometric and contrast stages.
+    pub fn decode(&self, frame_index: usize) -> anyhow::Result<Decoded> {
+        let options = self.options;
+        if self.cancellation.is_cancelled() {
+            return Err(anyhow!(Failure::Cancelled));
+        }
+        let current_image = self
+            .phases
+            .time(Phase::FrameIo, || self.source.frame(frame_index))
+            .context(Failure::Frames)?;
+        let started = Instant::now();
+        if current_image.dimensions() != self.source_dimensions {
+            return Err(
+                anyhow!("frame {} does not match size of first frame", frame_index)
+                    .context(Failure::Frames),
+            );
+        }
+        let (mut luma, mut alpha) = if current_image.color().has_alpha() {
+            let luma_alpha = current_image.to_luma_alpha8();
+            let alpha = GrayImage::from_fn(luma_alpha.width(), luma_alpha.height(), |x, y| {
+                Luma([luma_alpha.get_pixel(x, y).0[1]])
+            });
+            let background = match options.alpha_background {
+                AlphaBackground::Black | AlphaBackground::Keep => 0,
+                AlphaBackground::White => u16::from(u8::MAX),
+            };
+            let luma = GrayImage::from_fn(luma_alpha.width(), luma_alpha.height(), |x, y| {
+                let [luma, alpha] = luma_alpha.get_pixel(x, y).0;
+                let (luma, alpha) = (u16::from(luma), u16::from(alpha));
+                Luma([((luma * alpha + background * (255 - alpha)) / 255) as u8])
+            });
+            let alpha = Some(alpha).filter(|_| options.alpha_background == AlphaBackground::Keep);
+            (luma, alpha)
+        } else {
+            (current_image.to_luma8(), None)
+        };
+        luma = self.transform(luma, frame_index);
+        alpha = alpha.map(|alpha| self.transform(alpha, frame_index));
+        match options.contrast_stretch {
+            ContrastStretch::Off => {}
+            ContrastStretch::Frame => {
+                let range = luma_range(&luma);
+                stretch_contrast(&mut luma, range);
+            }
+            ContrastStretch::Global => stretch_contrast(&mut luma, self.global_luma_range.unwrap()),
+        }
+        self.phases.add(Phase::Preprocessing, started.elapsed());
+        Ok(Decoded { luma, alpha })
+    }
+
+    /// Applies the geometric stages to a channel of a frame.
+    fn transform(&self, mut luma: GrayImage, frame_index: usize) -> GrayImage {
+        let options = self.options;
+        if let Some(rect) = options.crop {
+            luma = rect.crop(&luma);
+        }
+        if options.flip_h {
+            luma = imageops::flip_horizontal(&luma);
+        }
+        if options.flip_v {
+            luma = imageops::flip_vertical(&luma);
+        }
+        match options.rotate {
+            Rotation::None => {}
+            Rotation::Cw90 => luma = imageops::rotate90(&luma),
+            Rotation::Cw180 => luma = imageops::rotate180(&luma),
+            Rotation::Cw270 => luma = imageops::rotate270(&luma),
+        }
+        if let Some(aspect) = options.letterbox {
+            luma = letterbox(&luma, aspect.padded(luma.dimensions()));
+        }
+        if let Some((width, height)) = self.resize {
+            luma = imageops::resize(&luma, width, height, options.filter.filter_type());
+        }
+        if let (Some(pan_zoom), Some(size)) = (&options.pan_zoom, options.pan_size) {
+            let cropped = pan_zoom.crop(&luma, frame_index, self.source.len());
+            luma = imageops::resize(&cropped, size.width, size.height, FilterType::Triangle);
+        }
+        luma
+    }
+}
+
+/// The preprocessing stages that depend on earlier frames, which turn decoded frames into the
+/// bitmap each frame should display. Frames must be processed in order.
+pub struct FramePipeline<'a> {
+    options: &'a Options,
+    threshold: u8,
+    phosphor_decay: PhosphorDecay,
+    denoise: Denoise,
+    change_budget: Option<ChangeBudget>,
+    last_luma: Option<GrayImage>,
+    /// The last frame drawn, before the stages that only change how it is displayed, which
+    /// transparent pixels keep showing.
+    last_frame: Option<Bitmap>,
+}
+
+impl<'a> FramePipeline<'a> {
+    /// Creates a pipeline for frames of `width` by `height` pixels. Each pixel takes
+    /// [`planes`] columns of the bitmaps produced, before any sync column is added.
+    pub fn new(options: &'a Options, width: usize, height: usize) -> Self {
+        Self {
+            options,
+            threshold: options.threshold,
+            phosphor_decay: PhosphorDecay::new(
+                options.phosphor_decay,
+                width * planes(options),
+                height,
+            ),
+            denoise: Denoise::new(options.initially_lit, width * planes(options), height),
+            change_budget: options.max_changes_per_frame.map(|max_changes| {
+                ChangeBudget::new(
+                    max_changes,
+                    options.initially_lit,
+                    width * planes(options),
+                    height,
+                )
+            }),
+            last_luma: None,
+            last_frame: None,
+        }
+    }
+
+    /// Lights pixels whose luma is at least `threshold`, instead of the one given by
+    /// `--threshold`.
+    pub fn with_threshold(mut self, threshold: u8) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn process(&mut self, frame_index: usize, decoded: Decoded) -> Bitmap {
+        let options = self.options;
+        let Decoded { mut luma, alpha } = decoded;
+        if options.blend {
+            let blended = match &self.last_luma {
+                Some(last_luma) => blend(last_luma, &luma),
+                None => luma.clone(),
+            };
+            self.last_luma = Some(luma);
+            luma = blended;
+        }
+        if options.invert {
+            imageops::invert(&mut luma);
+        }
+        if options.mode == Mode::Outline {
+            luma = sobel(&luma);
+        }
+        let threshold = if options.auto_threshold {
+            otsu_threshold(&luma).unwrap_or(self.threshold)
+        } else {
+            self.threshold
+        };
+        dither(&mut luma, options.dither, threshold);
+        let mut frame = quantize(&luma, options.origin, threshold, options.duotone);
+        if options.alpha_background == AlphaBackground::Keep {
+            if let Some(alpha) = alpha {
+                // Only fully transparent pixels keep what they showed, so that antialiased
+                // edges are still drawn.
+                let opaque = quantize(&alpha, options.origin, 1, None);
+                let planes = planes(options);
+                let last_frame = self.last_frame.get_or_insert_with(|| {
+                    vec![vec![options.initially_lit; frame[0].len()]; frame.len()]
+                });
+                for ((row, opaque_row), last_row) in frame.iter_mut().zip(&opaque).zip(last_frame) {
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        if !opaque_row[x / planes] {
+                            *pixel = last_row[x];
+                        }
+                    }
+                }
+            }
+            self.last_frame = Some(frame.clone());
+        }
+        if options.denoise {
+            self.denoise.apply(&mut frame);
+        }
+        self.phosphor_decay.apply(&mut frame);
+        if let Some(change_budget) = &mut self.change_budget {
+            change_budget.apply(&mut frame);
+        }
+        if options.sync_column {
+            add_sync_column(&mut frame, frame_index);
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_crop_stays_within_the_frame() {
+        let rect: Rect = "0.5,0,9.5,10".parse().unwrap();
+        assert!(rect.fits_within((10, 10)));
+        assert_eq!(rect.pixels(), (1, 0, 9, 10));
+        let cropped = rect.crop(&GrayImage::new(10, 10));
+        assert_eq!(cropped.dimensions(), (9, 10));
+        assert!(!"0.5,0,10,10".parse::<Rect>().unwrap().fits_within((10, 10)));
+    }
+
+    #[test]
+    fn letterbox_pads_the_short_side() {
+        let four_by_three = Aspect {
+            width: 4,
+            height: 3,
+        };
+        assert_eq!(four_by_three.padded((100, 100)), (133, 100));
+        assert_eq!(four_by_three.padded((400, 100)), (400, 300));
+        assert_eq!(four_by_three.padded((160, 120)), (160, 120));
+    }
+
+    #[test]
+    fn sobel_lights_edges_only() {
+        let image = GrayImage::from_fn(6, 3, |x, _| Luma([if x < 3 { 0 } else { 255 }]));
+        let edges = sobel(&image);
+        for y in 0..3 {
+            let row: Vec<u8> = (0..6).map(|x| edges.get_pixel(x, y).0[0]).collect();
+            assert_eq!(row, [0, 0, 255, 255, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn ordered_dither_masks_rank_every_cell_once() {
+        let mut bayer: Vec<u32> = (0..BAYER_SIZE * BAYER_SIZE)
+            .map(|i| bayer_rank(i % BAYER_SIZE, i / BAYER_SIZE))
+            .collect();
+        bayer.sort_unstable();
+        assert!(bayer.into_iter().eq(0..BAYER_SIZE * BAYER_SIZE));
+
+        let mut blue_noise = blue_noise().to_vec();
+        blue_noise.sort_unstable();
+        assert!(blue_noise
+            .into_iter()
+            .eq(0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE));
+    }
+
+    #[test]
+    fn bayer_spreads_consecutive_ranks() {
+        // Each quarter of the ranks takes one cell of every 2x2 block.
+        let mut block: Vec<u32> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .into_iter()
+            .map(|(x, y)| bayer_rank(x, y) / 16)
+            .collect();
+        block.sort_unstable();
+        assert_eq!(block, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn blue_noise_keeps_the_lowest_ranks_apart() {
+        let size = BLUE_NOISE_SIZE as usize;
+        let mask = blue_noise();
+        let lit: Vec<usize> = (0..mask.len()).filter(|&i| mask[i] < 64).collect();
+        for &a in &lit {
+            for &b in lit.iter().filter(|&&b| b != a) {
+                let distance = |a: usize, b: usize| {
+                    let d = a.abs_diff(b);
+                    d.min(size - d)
+                };
+                let (dx, dy) = (distance(a % size, b % size), distance(a / size, b / size));
+                assert!(dx > 1 || dy > 1, "cells {} and {} touch", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_the_average_shade() {
+        for (shade, lit) in [(0, 0), (64, 64), (128, 128), (255, 256)] {
+            let mut image = GrayImage::from_pixel(16, 16, Luma([shade]));
+            floyd_steinberg(&mut image, DEFAULT_THRESHOLD);
+            assert!(image.pixels().all(|pixel| matches!(pixel.0[0], 0 | 255)));
+            let count = image.pixels().filter(|pixel| pixel.0[0] == 255).count();
+            assert!(count.abs_diff(lit) <= 4, "{} lit at shade {}", count, shade);
+        }
+    }
+
+    #[test]
+    fn otsu_splits_two_shades() {
+        let image = GrayImage::from_fn(4, 4, |x, _| Luma([if x < 2 { 20 } else { 200 }]));
+        assert_eq!(otsu_threshold(&image), Some(21));
+        assert_eq!(
+            otsu_threshold(&GrayImage::from_pixel(4, 4, Luma([90]))),
+            None
+        );
+    }
+
+    #[test]
+    fn change_budget_keeps_clustered_changes_first() {
+        let mut budget = ChangeBudget::new(2, false, 5, 1);
+        let target = vec![vec![true, true, false, false, true]];
+        let mut frame = target.clone();
+        budget.apply(&mut frame);
+        assert_eq!(frame, [[true, true, false, false, false]]);
+        let mut frame = target.clone();
+        budget.apply(&mut frame);
+        assert_eq!(frame, target);
+    }
+
+    #[test]
+    fn denoise_hides_isolated_flips() {
+        let mut denoise = Denoise::new(false, 1, 1);
+        let shown: Vec<bool> = [0, 0, 1, 0, 1, 0, 0]
+            .into_iter()
+            .map(|lit| {
+                let mut frame = vec![vec![lit == 1]];
+                denoise.apply(&mut frame);
+                frame[0][0]
+            })
+            .collect();
+        assert_eq!(shown, [false; 7]);
+    }
+
+    #[test]
+    fn denoise_delays_held_changes_by_a_frame() {
+        let mut denoise = Denoise::new(false, 1, 1);
+        let shown: Vec<bool> = [1, 1, 1, 0, 0]
+            .into_iter()
+            .map(|lit| {
+                let mut frame = vec![vec![lit == 1]];
+                denoise.apply(&mut frame);
+                frame[0][0]
+            })
+            .collect();
+        assert_eq!(shown, [false, true, true, true, false]);
+    }
+}
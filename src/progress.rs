@@ -0,0 +1,41 @@
+//! Progress reporting, for frontends that show what a run is doing.
+
+/// Phase of a run. Frame I/O and preprocessing overlap with encoding, since frames are decoded
+/// ahead of the encoder on other threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    FrameIo,
+    Preprocessing,
+    Encoding,
+    Emission,
+    SaveIo,
+}
+
+/// Size of the circuit generated so far.
+#[derive(Clone, Copy, Debug)]
+pub struct Totals {
+    /// Index of the last frame encoded.
+    pub frame: usize,
+    pub components: usize,
+    pub wires: usize,
+    /// Estimated size added to the save, in bytes.
+    pub estimated_size: u64,
+}
+
+/// Receives progress events from a run. Every method does nothing by default.
+///
+/// Segments are encoded on threads of their own, so methods may be called from several threads
+/// at once, and frames may be reported out of order.
+pub trait Observer: Sync {
+    /// Called after frame `frame` of `frames` has been encoded into the circuit.
+    fn on_frame_encoded(&self, _frame: usize, _frames: usize) {}
+
+    /// Called after each frame has been encoded, with the size of the circuit so far.
+    fn on_totals(&self, _totals: &Totals) {}
+
+    /// Called when the run enters `phase`.
+    fn on_phase_change(&self, _phase: Phase) {}
+
+    /// Called with a problem that does not stop the run, but may make the result unusable.
+    fn on_warning(&self, _message: &str) {}
+}
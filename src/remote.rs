@@ -0,0 +1,85 @@
+//! Fetching of frames, frame lists, archives and videos over HTTP(S), through the `curl`
+//! command.
+
+use std::{
+    borrow::Cow,
+    fs::{create_dir_all, remove_dir_all, rename},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::bail;
+use logicworld_badapple::sidecar;
+
+/// Directory that downloaded files are cached in, relative to the working directory.
+const CACHE_DIR: &str = "remote-cache";
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Resolves `reference` against the URL of the list that contains it, the way a relative path
+/// is resolved against the directory of a local list.
+pub fn join(base: &str, reference: &str) -> String {
+    if is_url(reference) {
+        return reference.to_owned();
+    }
+    let directory = base
+        .rsplit_once('/')
+        .map_or(base, |(directory, _)| directory);
+    format!("{}/{}", directory, reference)
+}
+
+/// Downloads `url` into the local cache, unless it is already there, and returns its path.
+///
+/// Cached files are named after a hash of the URL and keep its extension, which is how frame
+/// formats are detected. The hash is stable, so the cache stays valid across builds.
+pub fn fetch(url: &str) -> anyhow::Result<PathBuf> {
+    let hash = sidecar::fnv1a(url.bytes());
+    let name = url.split(['?', '#']).next().unwrap_or(url);
+    let file_name = match Path::new(name).extension() {
+        Some(extension) => format!("{:016x}.{}", hash, extension.to_string_lossy()),
+        None => format!("{:016x}", hash),
+    };
+
+    let cache_dir = Path::new(CACHE_DIR);
+    let path = cache_dir.join(file_name);
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    create_dir_all(cache_dir)?;
+    // Downloads go to a temporary name first, so an interrupted download is never mistaken for
+    // a cached file.
+    let partial = path.with_extension("part");
+    eprintln!("downloading {}", url);
+    let status = Command::new("curl")
+        .args(["--fail", "--location", "--progress-bar", "--output"])
+        .arg(&partial)
+        .arg(url)
+        .status()?;
+    if !status.success() {
+        bail!("cannot download {}: curl exited with {}", url, status);
+    }
+    rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Returns `path` itself or, if it is a URL, the path it is downloaded to.
+pub fn localize(path: &Path) -> anyhow::Result<Cow<'_, Path>> {
+    match path.to_str().filter(|path| is_url(path)) {
+        Some(url) => Ok(Cow::Owned(fetch(url)?)),
+        None => Ok(Cow::Borrowed(path)),
+    }
+}
+
+/// Removes the download cache and everything in it.
+pub fn clean() -> anyhow::Result<()> {
+    match remove_dir_all(CACHE_DIR) {
+        Ok(()) => eprintln!("removed {}", CACHE_DIR),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => eprintln!("nothing to remove"),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
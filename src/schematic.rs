@@ -0,0 +1,124 @@
+//! A generic JSON schematic target, for driving displays in games and simulators other than
+//! Logic World.
+//!
+//! The schematic lists the boards, then the components with the index of the board they are
+//! placed on, then the wires between component pins:
+//!
+//! ```json
+//! {
+//!   "boards": [{"width": 4, "height": 2, "position": [0, 0, 0], "color": [51, 51, 51]}],
+//!   "components": [
+//!     {"kind": "delayer", "delay": 10, "board": 0, "position": [150, 150, 150], "rotation": null}
+//!   ],
+//!   "wires": [{"from": {"component": 0, "pin": "output"}, "to": {"component": 1, "pin": "input"}}]
+//! }
+//! ```
+//!
+//! Positions and sizes use the same units as Logic World saves.
+
+use std::io::Write;
+
+use crate::{
+    circuit::{Circuit, Kind, Pin},
+    emit::Target,
+    layout::Layout,
+};
+
+/// Writes a circuit as a JSON schematic to a writer.
+pub struct JsonSchematic<W> {
+    writer: W,
+}
+
+impl<W: Write> JsonSchematic<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Target for JsonSchematic<W> {
+    fn emit(&mut self, circuit: &Circuit, layout: &dyn Layout) -> anyhow::Result<()> {
+        let w = &mut self.writer;
+
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"boards\": [")?;
+        let boards = layout.boards();
+        for (index, board) in boards.iter().enumerate() {
+            writeln!(
+                w,
+                "    {{\"width\": {}, \"height\": {}, \"position\": {}, \"color\": {}}}{}",
+                board.width,
+                board.height,
+                array(&board.position),
+                array(&board.color),
+                separator(index, boards.len()),
+            )?;
+        }
+        writeln!(w, "  ],")?;
+
+        writeln!(w, "  \"components\": [")?;
+        for (index, component) in circuit.components.iter().enumerate() {
+            let placement = layout.place(component);
+            let (kind, delay) = match component.kind {
+                Kind::Delayer { delay } => ("delayer", Some(delay)),
+                Kind::Peg => ("peg", None),
+                Kind::Socket => ("socket", None),
+            };
+            write!(w, "    {{\"kind\": \"{}\", ", kind)?;
+            if let Some(delay) = delay {
+                write!(w, "\"delay\": {}, ", delay)?;
+            }
+            let rotation = match placement.rotation {
+                Some(rotation) => array(&rotation),
+                None => "null".to_owned(),
+            };
+            writeln!(
+                w,
+                "\"board\": {}, \"position\": {}, \"rotation\": {}}}{}",
+                placement.board,
+                array(&placement.position),
+                rotation,
+                separator(index, circuit.components.len()),
+            )?;
+        }
+        writeln!(w, "  ],")?;
+
+        writeln!(w, "  \"wires\": [")?;
+        for (index, wire) in circuit.wires.iter().enumerate() {
+            writeln!(
+                w,
+                "    {{\"from\": {}, \"to\": {}}}{}",
+                pin(wire.from),
+                pin(wire.to),
+                separator(index, circuit.wires.len()),
+            )?;
+        }
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+fn array<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(T::to_string).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn pin(pin: Pin) -> String {
+    let (node, name) = match pin {
+        Pin::Input(node) => (node, "input"),
+        Pin::Output(node) => (node, "output"),
+    };
+    format!("{{\"component\": {}, \"pin\": \"{}\"}}", node.0, name)
+}
+
+fn separator(index: usize, len: usize) -> &'static str {
+    if index + 1 < len {
+        ","
+    } else {
+        ""
+    }
+}
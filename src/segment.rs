@@ -0,0 +1,357 @@
+//! Encoding of a contiguous range of frames into a fragment of the circuit.
+//!
+//! Long videos are split into segments that are encoded in parallel, each into its own
+//! [fragment](Circuit::fragment), and then stitched together in order. Each column's signal chain
+//! runs through every frame, so a segment cannot know where the chains end when it starts.
+//! Wires to those ends are recorded instead, and connected when the segment is stitched on.
+
+use std::{
+    mem,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver},
+    },
+    thread,
+    time::Instant,
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    cancel::CancellationToken,
+    circuit::{Circuit, Kind, NodeId, Pin, Slot},
+    encode::Options,
+    estimate::{self, RunningEstimate},
+    failure::Failure,
+    phases::PhaseTimes,
+    preprocess::{self, AlphaBackground, Bitmap, Decoded, FrameDecoder, FramePipeline},
+    progress::{Observer, Phase, Totals},
+    sidecar,
+    timing::{Timing, CHUNK_DELAY},
+};
+
+/// Size of the circuit built so far, summed over every segment.
+pub struct Progress {
+    components: AtomicUsize,
+    wires: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+impl Progress {
+    /// Starts counting from `circuit`, as placed on `boards` boards.
+    pub fn new(circuit: &Circuit, boards: usize) -> Self {
+        Self {
+            components: AtomicUsize::new(circuit.components.len()),
+            wires: AtomicUsize::new(circuit.wires.len()),
+            bytes: AtomicU64::new(estimate::file_size(circuit, boards)),
+        }
+    }
+}
+
+/// Everything segments are encoded from, shared between them.
+pub struct Encoder<'a> {
+    pub options: &'a Options,
+    pub timing: &'a Timing,
+    pub phases: &'a PhaseTimes,
+    pub observer: &'a dyn Observer,
+    pub cancellation: &'a CancellationToken,
+    pub decoder: &'a FrameDecoder<'a>,
+    /// Frames shown before the source frames, which bypass preprocessing.
+    pub leading_frames: &'a [Bitmap],
+    /// Size of the decoded source frames.
+    pub width: usize,
+    pub height: usize,
+    /// Number of display columns, including planes and any sync column.
+    pub display_width: usize,
+    pub frame_count: usize,
+    /// The circuit before any frame is encoded, holding the timing chains and sockets.
+    pub circuit: &'a Circuit,
+    /// The timing chain delayers of each row.
+    pub row_frame_delayers: &'a [Vec<NodeId>],
+    /// Number of threads decoding frames for each segment.
+    pub jobs: usize,
+    pub progress: &'a Progress,
+}
+
+/// A range of frames encoded into a fragment of the circuit.
+pub struct Segment {
+    pub circuit: Circuit,
+    /// Wires from components of the fragment to the end of a column's chain as it was when the
+    /// segment started, as `(from, row, column)`.
+    pub pending: Vec<(Pin, usize, usize)>,
+    /// Where each column's chain ends after the segment, if the segment extended it.
+    pub tails: Vec<Vec<Option<NodeId>>>,
+    pub toggles: usize,
+    pub frame_hashes: Vec<u64>,
+    /// Frames at which chunking delayers were inserted.
+    pub chunk_frames: Vec<usize>,
+    pub junction_pegs_saved: usize,
+}
+
+impl Segment {
+    /// Wires `from` to the end of a column's chain, which may lie before the segment.
+    fn connect(&mut self, from: Pin, tail: Option<NodeId>, row: usize, column: usize) {
+        match tail {
+            Some(tail) => self.circuit.wire(from, Pin::Input(tail)),
+            None => self.pending.push((from, row, column)),
+        }
+    }
+}
+
+impl Encoder<'_> {
+    /// Encodes `frames` into a fragment of the circuit.
+    pub fn encode(&self, frames: Range<usize>) -> anyhow::Result<Segment> {
+        let options = self.options;
+        let (height, display_width) = (self.height, self.display_width);
+        let leading = self.leading_frames.len();
+        let source_frames =
+            frames.start.saturating_sub(leading)..frames.end.saturating_sub(leading);
+        // Some stages of the pipeline depend on earlier frames, so it is first run over enough of
+        // the frames before the segment to reproduce its state, ending with the frame displayed
+        // when the segment starts.
+        let warm_up = match source_frames.start.checked_sub(1) {
+            // Deferred changes, transparent pixels and suppressed flips keep what was shown
+            // however long ago, so the state can only be reproduced from the first frame.
+            Some(_)
+                if options.max_changes_per_frame.is_some()
+                    || options.alpha_background == AlphaBackground::Keep
+                    || options.denoise =>
+            {
+                0..source_frames.start
+            }
+            Some(keyframe) => {
+                let history = options.phosphor_decay + usize::from(options.blend);
+                keyframe.saturating_sub(history)..source_frames.start
+            }
+            None => 0..0,
+        };
+        let decoded_frames = warm_up.start..source_frames.end;
+
+        let mut segment = Segment {
+            circuit: self.circuit.fragment(),
+            pending: Vec::new(),
+            tails: vec![vec![None; display_width]; height],
+            toggles: 0,
+            frame_hashes: Vec::with_capacity(frames.len()),
+            chunk_frames: Vec::new(),
+            junction_pegs_saved: 0,
+        };
+        let mut pipeline = FramePipeline::new(options, self.width, height);
+        let mut running_estimate = RunningEstimate::default();
+        let mut estimated_bytes = 0;
+
+        // Frames are decoded by `jobs` threads, each taking every `jobs`th frame and queueing its
+        // share of up to `prefetch` frames ahead of circuit generation. The stateful preprocessing
+        // stages then run in order on this thread.
+        thread::scope(|scope| -> anyhow::Result<()> {
+            let jobs = self.jobs;
+            let capacity = options.prefetch.div_ceil(jobs);
+            let decoded: Vec<Receiver<anyhow::Result<Decoded>>> = (0..jobs)
+                .map(|job| {
+                    let (sender, receiver) = sync_channel(capacity);
+                    let (decoder, decoded_frames) = (self.decoder, decoded_frames.clone());
+                    scope.spawn(move || {
+                        for frame_index in decoded_frames.skip(job).step_by(jobs) {
+                            let frame = decoder.decode(frame_index);
+                            let failed = frame.is_err();
+                            if sender.send(frame).is_err() || failed {
+                                break;
+                            }
+                        }
+                    });
+                    receiver
+                })
+                .collect();
+            let mut process = |source_index: usize| -> anyhow::Result<Bitmap> {
+                let frame = decoded[(source_index - decoded_frames.start) % jobs].recv()??;
+                Ok(self.phases.time(Phase::Preprocessing, || {
+                    pipeline.process(source_index, frame)
+                }))
+            };
+
+            // Pixels are toggled, so the first frame is painted relative to the display's state
+            // when the segment starts.
+            let mut last_frame: Bitmap = match frames.start.checked_sub(1) {
+                None => vec![vec![options.initially_lit; display_width]; height],
+                Some(previous) if previous < leading => self.leading_frames[previous].clone(),
+                Some(_) => {
+                    let mut keyframe = None;
+                    for source_index in warm_up.clone() {
+                        keyframe = Some(process(source_index)?);
+                    }
+                    keyframe.expect("the warm-up ends with the frame before the segment")
+                }
+            };
+
+            for frame_index in frames.clone() {
+                if self.cancellation.is_cancelled() {
+                    return Err(anyhow!(Failure::Cancelled));
+                }
+                let z = (frame_index + 1) * 2;
+                // The countdown and self-test frames come first, and bypass preprocessing.
+                let current_frame = match frame_index.checked_sub(leading) {
+                    None => self.leading_frames[frame_index].clone(),
+                    Some(source_index) => process(source_index)?,
+                };
+                let encode_started = Instant::now();
+                let (components_before, wires_before) = (
+                    segment.circuit.components.len(),
+                    segment.circuit.wires.len(),
+                );
+                segment
+                    .frame_hashes
+                    .push(sidecar::frame_hash(&current_frame));
+
+                // Pixels only ever toggle towards the current frame, so the display shows exactly
+                // this frame once it is reached.
+                if let Some(snapshot) = options
+                    .snapshot
+                    .as_ref()
+                    .filter(|_| options.snapshot_frame == Some(frame_index))
+                {
+                    preprocess::to_image(&current_frame)
+                        .save(snapshot)
+                        .with_context(|| format!("cannot write snapshot {:?}", snapshot))
+                        .context(Failure::Write)?;
+                }
+
+                // Force inserting a delayer every once in a while, to "chunk" the huge nets made
+                // by pixel signal wires and effectively reduce UPS.
+                // The additional delay caused by these delayers is compensated for in the timing
+                // delayers.
+                if self.timing.is_chunk_frame(frame_index) {
+                    segment.chunk_frames.push(frame_index);
+                    if options.chunk_taps {
+                        for (y, frame_delayers) in self.row_frame_delayers.iter().enumerate() {
+                            let tap = segment.circuit.add(Kind::Peg, Slot::ChunkTap { row: y, z });
+                            segment
+                                .circuit
+                                .wire(Pin::Input(frame_delayers[z]), Pin::Input(tap));
+                        }
+                    }
+                    for y in 0..height {
+                        for x in 0..display_width {
+                            let chunk_delayer = segment.circuit.add(
+                                Kind::Delayer { delay: CHUNK_DELAY },
+                                Slot::Junction {
+                                    row: y,
+                                    column: x,
+                                    z,
+                                },
+                            );
+                            segment.connect(Pin::Output(chunk_delayer), segment.tails[y][x], y, x);
+                            // Later pixels must pass through the chunking delayer even if this
+                            // pixel does not change on this frame.
+                            segment.tails[y][x] = Some(chunk_delayer);
+                        }
+                    }
+                }
+
+                for y in 0..height {
+                    let mut row_last_delayer = self.row_frame_delayers[y][z];
+                    // With tidy wiring, the row's pixel chain is fed through a junction peg in line
+                    // with the pixel delayers, so the timing tap and the chain form straight runs.
+                    let mut row_bus_started = !options.tidy_wiring;
+                    for x in 0..display_width {
+                        if current_frame[y][x] != last_frame[y][x] {
+                            segment.toggles += 1;
+                            if !row_bus_started {
+                                let junction =
+                                    segment.circuit.add(Kind::Peg, Slot::RowBus { row: y, z });
+                                segment
+                                    .circuit
+                                    .wire(Pin::Input(row_last_delayer), Pin::Input(junction));
+                                row_last_delayer = junction;
+                                row_bus_started = true;
+                            }
+
+                            let pixel_delayer = segment.circuit.add(
+                                Kind::Delayer {
+                                    delay: self.timing.pixel_delay(),
+                                },
+                                Slot::Pixel {
+                                    row: y,
+                                    column: x,
+                                    z,
+                                },
+                            );
+
+                            // A component already occupying the junction slot (such as a
+                            // chunking delayer) replaces the junction peg. Without junction pegs,
+                            // the delayer output is instead wired straight to the column's socket
+                            // or latest chunking delayer, which is also a legal input.
+                            let junction_slot = Slot::Junction {
+                                row: y,
+                                column: x,
+                                z,
+                            };
+                            let tail = segment.tails[y][x];
+                            let pixel_peg =
+                                if let Some(existing) = segment.circuit.at(junction_slot) {
+                                    Some(existing)
+                                } else if options.no_junction_pegs {
+                                    segment.junction_pegs_saved += 1;
+                                    tail
+                                } else {
+                                    Some(segment.circuit.add(Kind::Peg, junction_slot))
+                                };
+
+                            segment
+                                .circuit
+                                .wire(Pin::Input(row_last_delayer), Pin::Input(pixel_delayer));
+                            segment.connect(Pin::Output(pixel_delayer), pixel_peg, y, x);
+
+                            // This wire is not needed if the last peg was reused
+                            if let Some(pixel_peg) = pixel_peg.filter(|&peg| Some(peg) != tail) {
+                                segment.connect(Pin::Input(pixel_peg), tail, y, x);
+                            }
+
+                            row_last_delayer = pixel_delayer;
+                            segment.tails[y][x] = pixel_peg;
+                        }
+                    }
+                }
+
+                last_frame = current_frame;
+                let bytes = running_estimate.update(&segment.circuit, 0);
+                let (components, wires, bytes) = (
+                    segment.circuit.components.len() - components_before,
+                    segment.circuit.wires.len() - wires_before,
+                    bytes - mem::replace(&mut estimated_bytes, bytes),
+                );
+                let totals = Totals {
+                    frame: frame_index,
+                    components: self
+                        .progress
+                        .components
+                        .fetch_add(components, Ordering::Relaxed)
+                        + components,
+                    wires: self.progress.wires.fetch_add(wires, Ordering::Relaxed) + wires,
+                    estimated_size: self.progress.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes,
+                };
+                self.phases.add(Phase::Encoding, encode_started.elapsed());
+                self.observer
+                    .on_frame_encoded(frame_index, self.frame_count);
+                self.observer.on_totals(&totals);
+
+                // Catches a run that is far over budget long before it would otherwise finish.
+                if let Some(abort_size_mib) = options.abort_size_mib {
+                    if totals.estimated_size > abort_size_mib << 20 {
+                        return Err(anyhow!(
+                            "estimated output exceeds {} MiB after frame {} of {}",
+                            abort_size_mib,
+                            frame_index,
+                            self.frame_count
+                        )
+                        .context(Failure::Placement));
+                    }
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(segment)
+    }
+}
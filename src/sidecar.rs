@@ -0,0 +1,241 @@
+//! Metadata recorded next to an injected save, describing how it was generated.
+//!
+//! The file uses the same `Key: value` layout as the save's own `meta.succ`, and doubles as the
+//! manifest used to find a previous injection in a save.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::layout::Bounds;
+
+/// What an injection produced.
+pub struct Injection {
+    pub width: usize,
+    pub height: usize,
+    pub frames: usize,
+    /// Name of the video, if it has one.
+    pub title: Option<String>,
+    /// Number of pixel changes across all frames, each costing a delayer, a peg and wires.
+    pub toggles: usize,
+    /// Space covered by the generated boards, in world position units.
+    pub bounds: Bounds,
+    /// Content hash of each displayed frame, in order. See [`frame_hash`].
+    pub frame_hashes: Vec<u64>,
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher stays the same across builds, so
+/// hashes recorded by one version can be compared by another.
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Hashes the pixels of a displayed frame, given as rows of pixels.
+///
+/// This is [`fnv1a`] over one byte per pixel.
+pub fn frame_hash(rows: &[Vec<bool>]) -> u64 {
+    // Separate rows, so that the same pixels split differently do not collide.
+    fnv1a(
+        rows.iter()
+            .flat_map(|row| row.iter().map(|&pixel| u8::from(pixel)).chain([2])),
+    )
+}
+
+/// Returns the index of the first frame that differs between two recorded frame sequences,
+/// or `None` if they are identical. A frame present in only one of them counts as different.
+pub fn first_changed_frame(previous: &[u64], current: &[u64]) -> Option<usize> {
+    previous
+        .iter()
+        .zip(current)
+        .position(|(previous, current)| previous != current)
+        .or_else(|| (previous.len() != current.len()).then_some(previous.len().min(current.len())))
+}
+
+/// A sidecar read back from disk.
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub injected_at: u64,
+    pub tool_version: String,
+    pub arguments: String,
+    pub injection: Injection,
+}
+
+pub fn sidecar_path(save_path: &Path) -> PathBuf {
+    save_path.with_extension("badapple.succ")
+}
+
+/// Looks for a previous injection into the save at `save_path`, returning its recorded
+/// parameters, frame count and bounding box.
+pub fn find_injection(save_path: &Path) -> anyhow::Result<Option<Record>> {
+    read(&sidecar_path(save_path))
+}
+
+/// Reads a previously written sidecar, if there is one. Missing or malformed fields are left at
+/// their defaults.
+pub fn read(path: &Path) -> anyhow::Result<Option<Record>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let fields: HashMap<&str, &str> = contents
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .collect();
+    let number = |key: &str| -> u64 {
+        fields
+            .get(key)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    };
+    let text = |key: &str| fields.get(key).copied().unwrap_or_default().to_string();
+
+    let bounds: Vec<i32> = fields
+        .get("Bounds")
+        .map(|value| value.split(' ').filter_map(|n| n.parse().ok()).collect())
+        .unwrap_or_default();
+    let bounds = match bounds[..] {
+        [x0, y0, z0, x1, y1, z1] => Bounds {
+            min: [x0, y0, z0],
+            max: [x1, y1, z1],
+        },
+        _ => Bounds {
+            min: [0; 3],
+            max: [0; 3],
+        },
+    };
+
+    let frame_hashes = fields
+        .get("FrameHashes")
+        .map(|value| {
+            value
+                .split(' ')
+                .filter_map(|hash| u64::from_str_radix(hash, 16).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Some(Record {
+        injected_at: number("InjectedAt"),
+        tool_version: text("ToolVersion"),
+        arguments: text("Arguments"),
+        injection: Injection {
+            width: number("Width") as usize,
+            height: number("Height") as usize,
+            frames: number("Frames") as usize,
+            title: fields.get("Title").map(|title| title.to_string()),
+            toggles: number("Toggles") as usize,
+            bounds,
+            frame_hashes,
+        },
+    }))
+}
+
+/// Records an injection made with the given command-line `arguments`.
+pub fn write(path: &Path, arguments: &str, injection: &Injection) -> anyhow::Result<()> {
+    let injected_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let Bounds { min, max } = injection.bounds;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "InjectedAt: {}", injected_at)?;
+    writeln!(writer, "ToolVersion: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer, "Arguments: {}", arguments)?;
+    if let Some(title) = &injection.title {
+        // Lines are the only separator, so a title cannot span several.
+        writeln!(writer, "Title: {}", title.replace(['\r', '\n'], " "))?;
+    }
+    writeln!(writer, "Width: {}", injection.width)?;
+    writeln!(writer, "Height: {}", injection.height)?;
+    writeln!(writer, "Frames: {}", injection.frames)?;
+    writeln!(writer, "Toggles: {}", injection.toggles)?;
+    writeln!(
+        writer,
+        "Bounds: {} {} {} {} {} {}",
+        min[0], min[1], min[2], max[0], max[1], max[2]
+    )?;
+    let frame_hashes: Vec<String> = injection
+        .frame_hashes
+        .iter()
+        .map(|hash| format!("{:016x}", hash))
+        .collect();
+    writeln!(writer, "FrameHashes: {}", frame_hashes.join(" "))?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn sidecars_read_back_what_was_written() {
+        let injection = Injection {
+            width: 48,
+            height: 36,
+            frames: 3,
+            title: Some("Bad Apple!!".to_string()),
+            toggles: 1234,
+            bounds: Bounds {
+                min: [-10, 0, 5],
+                max: [20, 7, 300],
+            },
+            frame_hashes: vec![0, 0xdead_beef, u64::MAX],
+        };
+        let path = env::temp_dir().join(format!("sidecar-test-{}.succ", process::id()));
+        write(&path, "--title Bad Apple!! world", &injection).unwrap();
+        let record = read(&path);
+        fs::remove_file(&path).unwrap();
+
+        let record = record.unwrap().expect("sidecar was written");
+        assert_eq!(record.tool_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(record.arguments, "--title Bad Apple!! world");
+        let read_back = &record.injection;
+        assert_eq!(
+            (read_back.width, read_back.height, read_back.frames),
+            (48, 36, 3)
+        );
+        assert_eq!(read_back.title, injection.title);
+        assert_eq!(read_back.toggles, 1234);
+        assert_eq!(read_back.bounds, injection.bounds);
+        assert_eq!(read_back.frame_hashes, injection.frame_hashes);
+    }
+
+    #[test]
+    fn missing_sidecars_are_not_an_error() {
+        let path = env::temp_dir().join(format!("sidecar-missing-{}.succ", process::id()));
+        assert!(read(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn first_changed_frame_compares_in_order() {
+        assert_eq!(first_changed_frame(&[1, 2, 3], &[1, 2, 3]), None);
+        assert_eq!(first_changed_frame(&[1, 2, 3], &[1, 5, 3]), Some(1));
+        assert_eq!(first_changed_frame(&[1, 2, 3], &[1, 2]), Some(2));
+        assert_eq!(first_changed_frame(&[1, 2], &[1, 2, 3, 4]), Some(2));
+        assert_eq!(first_changed_frame(&[], &[]), None);
+    }
+
+    #[test]
+    fn frame_hashes_tell_rows_apart() {
+        let frame = vec![vec![true, false], vec![false, true]];
+        assert_ne!(
+            frame_hash(&frame),
+            frame_hash(&[vec![true, false, false, true]])
+        );
+        assert_ne!(
+            frame_hash(&frame),
+            frame_hash(&[vec![true, true], vec![false, false]])
+        );
+    }
+}
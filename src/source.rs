@@ -0,0 +1,218 @@
+//! Sources of the frames to be played back.
+
+use std::time::Duration;
+
+use image::DynamicImage;
+
+/// An ordered sequence of frames.
+///
+/// Frames are requested by index, possibly out of order and from several threads at once, so
+/// that they can be decoded in parallel. Any source can be [encoded](crate::encode::encode).
+pub trait FrameSource: Sync {
+    /// Number of frames in the source.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Loads the frame at `index`.
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage>;
+
+    /// How long the frame at `index` is meant to be shown, if the source specifies it.
+    fn duration(&self, _index: usize) -> Option<Duration> {
+        None
+    }
+}
+
+/// Frames that are already in memory.
+impl FrameSource for Vec<DynamicImage> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        Ok(self[index].clone())
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        (**self).frame(index)
+    }
+
+    fn duration(&self, index: usize) -> Option<Duration> {
+        (**self).duration(index)
+    }
+}
+
+/// Every `step`-th frame of another source, starting with the first. Each frame lasts as long
+/// as the frames of the inner source it stands in for.
+pub struct Decimated<S> {
+    source: S,
+    step: usize,
+}
+
+impl<S: FrameSource> Decimated<S> {
+    pub fn new(source: S, step: usize) -> Self {
+        Self { source, step }
+    }
+}
+
+impl<S: FrameSource> FrameSource for Decimated<S> {
+    fn len(&self) -> usize {
+        self.source.len().div_ceil(self.step)
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        self.source.frame(index * self.step)
+    }
+
+    fn duration(&self, index: usize) -> Option<Duration> {
+        let start = index * self.step;
+        let end = (start + self.step).min(self.source.len());
+        (start..end).map(|inner| self.source.duration(inner)).sum()
+    }
+}
+
+/// The frames of another source from `start` up to, but not including, `end`.
+pub struct Trimmed<S> {
+    source: S,
+    start: usize,
+    end: usize,
+}
+
+impl<S: FrameSource> Trimmed<S> {
+    /// Trims `source` to `start..end`, clamped to the frames it has. `None` keeps every frame
+    /// from `start` onwards.
+    pub fn new(source: S, start: usize, end: Option<usize>) -> Self {
+        let end = end.map_or(source.len(), |end| end.min(source.len()));
+        Self {
+            start: start.min(end),
+            end,
+            source,
+        }
+    }
+}
+
+impl<S: FrameSource> FrameSource for Trimmed<S> {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        self.source.frame(self.start + index)
+    }
+
+    fn duration(&self, index: usize) -> Option<Duration> {
+        self.source.duration(self.start + index)
+    }
+}
+
+/// Another source converted from `from_fps` to `to_fps` by repeating or dropping frames.
+///
+/// Each frame shows the source frame that would be on screen when it starts, so frames are
+/// repeated or dropped as the timing error accumulates, and it never exceeds one source frame.
+pub struct Resampled<S> {
+    source: S,
+    from_fps: f64,
+    to_fps: f64,
+}
+
+impl<S: FrameSource> Resampled<S> {
+    pub fn new(source: S, from_fps: f64, to_fps: f64) -> Self {
+        Self {
+            source,
+            from_fps,
+            to_fps,
+        }
+    }
+
+    fn source_index(&self, index: usize) -> usize {
+        // The epsilon keeps exact ratios from rounding down a whole frame.
+        (index as f64 * self.from_fps / self.to_fps + 1e-9).floor() as usize
+    }
+}
+
+impl<S: FrameSource> FrameSource for Resampled<S> {
+    fn len(&self) -> usize {
+        (self.source.len() as f64 * self.to_fps / self.from_fps).ceil() as usize
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        let source_index = self.source_index(index).min(self.source.len() - 1);
+        self.source.frame(source_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// Frames whose single pixel holds their index, each lasting one more millisecond than the
+    /// last.
+    struct Numbered(usize);
+
+    impl FrameSource for Numbered {
+        fn len(&self) -> usize {
+            self.0
+        }
+
+        fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+            Ok(DynamicImage::ImageLuma8(GrayImage::from_pixel(
+                1,
+                1,
+                Luma([index as u8]),
+            )))
+        }
+
+        fn duration(&self, index: usize) -> Option<Duration> {
+            Some(Duration::from_millis(index as u64 + 1))
+        }
+    }
+
+    fn indices(source: &dyn FrameSource) -> Vec<u8> {
+        (0..source.len())
+            .map(|index| source.frame(index).unwrap().to_luma8().get_pixel(0, 0).0[0])
+            .collect()
+    }
+
+    #[test]
+    fn trimmed_keeps_the_frames_in_range() {
+        assert_eq!(indices(&Trimmed::new(Numbered(10), 2, Some(5))), [2, 3, 4]);
+        assert_eq!(indices(&Trimmed::new(Numbered(10), 7, None)), [7, 8, 9]);
+        assert_eq!(indices(&Trimmed::new(Numbered(10), 8, Some(20))), [8, 9]);
+        assert!(Trimmed::new(Numbered(10), 20, Some(5)).is_empty());
+    }
+
+    #[test]
+    fn decimated_frames_last_as_long_as_those_they_replace() {
+        let decimated = Decimated::new(Trimmed::new(Numbered(10), 2, Some(9)), 3);
+        assert_eq!(indices(&decimated), [2, 5, 8]);
+        let durations: Vec<_> = (0..decimated.len())
+            .map(|index| decimated.duration(index))
+            .collect();
+        assert_eq!(
+            durations,
+            [12, 21, 9].map(|millis| Some(Duration::from_millis(millis)))
+        );
+    }
+
+    #[test]
+    fn resampled_repeats_or_drops_frames() {
+        assert_eq!(
+            indices(&Resampled::new(Numbered(10), 30.0, 20.0)),
+            [0, 1, 3, 4, 6, 7, 9]
+        );
+        assert_eq!(
+            indices(&Resampled::new(Numbered(3), 24.0, 60.0)),
+            [0, 0, 0, 1, 1, 2, 2, 2]
+        );
+    }
+}
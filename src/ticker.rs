@@ -0,0 +1,121 @@
+//! Horizontally scrolling text, generated without any external video tooling.
+
+use image::{imageops, DynamicImage, GrayImage, Luma};
+
+use crate::source::FrameSource;
+
+/// Height of the font, and so of the generated frames, in pixels.
+pub const GLYPH_HEIGHT: u32 = 7;
+
+/// Width of each glyph, in pixels, not counting the column separating it from the next.
+const GLYPH_WIDTH: u32 = 5;
+
+/// Rows of a 5x7 glyph from top to bottom, with the leftmost pixel in bit 4.
+type Glyph = [u8; GLYPH_HEIGHT as usize];
+
+/// Returns the glyph for a character. Letters are shown in upper case, and characters without a
+/// glyph are shown as `?`.
+fn glyph(c: char) -> Glyph {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        _ => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+/// Renders `text` in lit pixels on a dark strip [`GLYPH_HEIGHT`] pixels high, with a blank
+/// column after each character.
+pub fn render(text: &str) -> GrayImage {
+    let text_width = text.chars().count() as u32 * (GLYPH_WIDTH + 1);
+    let mut image = GrayImage::new(text_width, GLYPH_HEIGHT);
+    for (i, c) in text.chars().enumerate() {
+        let left = i as u32 * (GLYPH_WIDTH + 1);
+        for (y, row) in glyph(c).iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (0x10 >> x) != 0 {
+                    image.put_pixel(left + x, y as u32, Luma([255]));
+                }
+            }
+        }
+    }
+    image
+}
+
+/// Text scrolling right to left across a window `width` pixels wide, moving `speed` pixels per
+/// frame. The text enters from the right edge and the last frame is blank again once it has
+/// left on the left.
+pub struct Ticker {
+    /// The rendered text, padded on both sides with a blank window.
+    strip: GrayImage,
+    width: u32,
+    speed: u32,
+}
+
+impl Ticker {
+    pub fn new(text: &str, width: u32, speed: u32) -> Self {
+        assert!(speed > 0, "ticker speed must be positive");
+        let text = render(text);
+        let mut strip = GrayImage::new(width + text.width() + width, GLYPH_HEIGHT);
+        imageops::replace(&mut strip, &text, width, 0);
+        Self {
+            strip,
+            width,
+            speed,
+        }
+    }
+}
+
+impl FrameSource for Ticker {
+    fn len(&self) -> usize {
+        ((self.strip.width() - self.width) / self.speed + 1) as usize
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        let left = index as u32 * self.speed;
+        let frame = GrayImage::from_fn(self.width, GLYPH_HEIGHT, |x, y| {
+            *self.strip.get_pixel(left + x, y)
+        });
+        Ok(DynamicImage::ImageLuma8(frame))
+    }
+}
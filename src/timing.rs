@@ -0,0 +1,342 @@
+//! Frame timing: how source frames map onto the timing chain, and when they reach the display.
+
+use std::time::Duration;
+
+use crate::circuit::{Circuit, Kind, NodeId, Pin, Slot};
+
+/// Smallest delay a timing delayer may have. One tick is subtracted from some timing delayers
+/// to compensate for chunking delayers, and a delayer cannot go below one tick.
+pub const MIN_HALF_PERIOD: u32 = 2;
+
+/// Delay of the per-pixel delayers that forward the timing pulse into a pixel column, without
+/// guard ticks.
+pub const PIXEL_DELAY: u32 = 1;
+
+/// Delay of the chunking delayers inserted into every pixel column.
+pub const CHUNK_DELAY: u32 = 1;
+
+/// Default number of frames between chunking delayers.
+pub const CHUNK_INTERVAL: usize = 200;
+
+/// How source frames are mapped onto the timing chain.
+#[derive(Clone)]
+pub struct Timing {
+    /// Only every `decimation`-th source frame is displayed.
+    pub decimation: usize,
+    /// Delay of each of the two timing delayers per frame, in ticks.
+    pub half_period: u32,
+    /// Extra ticks between a frame's timing pulse and its pixel updates.
+    pub guard_ticks: u32,
+    /// Number of frames between chunking delayers.
+    pub chunk_interval: usize,
+    /// Frames at which chunking delayers are inserted, in increasing order, if not every
+    /// `chunk_interval`th frame.
+    pub chunk_frames: Option<Vec<usize>>,
+    /// Extra ticks between the start pulse and the first frame.
+    pub start_delay: u32,
+    /// Period of each displayed frame, in ticks, for sources whose frames last different
+    /// lengths of time. Frames past the end use the uniform [`frame_period`](Self::frame_period).
+    pub frame_periods: Vec<u32>,
+}
+
+impl Timing {
+    /// Picks the frame period closest to real-time playback of `source_fps` at `tick_rate`,
+    /// dropping frames if the source is too fast for the shortest possible period.
+    pub fn from_fps(source_fps: f64, tick_rate: f64) -> Self {
+        let decimation = (2.0 * MIN_HALF_PERIOD as f64 * source_fps / tick_rate)
+            .ceil()
+            .max(1.0) as usize;
+        let half_period = (tick_rate * decimation as f64 / (2.0 * source_fps))
+            .round()
+            .max(MIN_HALF_PERIOD as f64) as u32;
+        Self {
+            decimation,
+            half_period,
+            guard_ticks: 0,
+            chunk_interval: CHUNK_INTERVAL,
+            chunk_frames: None,
+            start_delay: 0,
+            frame_periods: Vec::new(),
+        }
+    }
+
+    pub fn effective_fps(&self, tick_rate: f64) -> f64 {
+        tick_rate / self.frame_period() as f64
+    }
+
+    pub fn frame_period(&self) -> u32 {
+        2 * self.half_period
+    }
+
+    /// Ticks from the display of `frame` to the display of the next frame.
+    pub fn period_of(&self, frame: usize) -> u32 {
+        self.frame_periods
+            .get(frame)
+            .copied()
+            .unwrap_or_else(|| self.frame_period())
+    }
+
+    /// Delay of the per-pixel delayers, including any guard ticks.
+    ///
+    /// The guard shifts every frame's updates by the same amount, so the frame period and
+    /// playback speed are unchanged.
+    pub fn pixel_delay(&self) -> u32 {
+        PIXEL_DELAY + self.guard_ticks
+    }
+
+    /// Whether chunking delayers are inserted into the pixel columns at `frame`.
+    pub fn is_chunk_frame(&self, frame: usize) -> bool {
+        match &self.chunk_frames {
+            Some(chunk_frames) => chunk_frames.binary_search(&frame).is_ok(),
+            None => (frame + 1) % self.chunk_interval == 0,
+        }
+    }
+
+    /// Delay of the `z`th delayer in the timing chain.
+    pub fn delayer_delay(&self, z: usize) -> u32 {
+        // Subtract a tick from timing delayers that correspond to chunking delayers. Frame `f` is
+        // driven from step `2 * (f + 1)`, so its chunking delayers are compensated for in the
+        // delayer just before it.
+        let chunk_compensation = if z % 2 == 1 && self.is_chunk_frame(z / 2) {
+            CHUNK_DELAY
+        } else {
+            0
+        };
+        // Steps 2f + 2 and 2f + 3 lead from the pulse of frame f to the pulse of frame f + 1, and
+        // split its period between them. The steps before the first frame use the uniform period,
+        // and the first of them also waits out the start delay.
+        let half_period = match z.checked_sub(2) {
+            Some(step) => {
+                let period = self.period_of(step / 2);
+                if step % 2 == 0 {
+                    period / 2
+                } else {
+                    period - period / 2
+                }
+            }
+            None if z == 0 => self.half_period + self.start_delay,
+            None => self.half_period,
+        };
+        half_period - chunk_compensation
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            decimation: 1,
+            half_period: 10,
+            guard_ticks: 0,
+            chunk_interval: CHUNK_INTERVAL,
+            chunk_frames: None,
+            start_delay: 0,
+            frame_periods: Vec::new(),
+        }
+    }
+}
+
+/// Converts how long each frame is meant to be shown into frame periods at `tick_rate`. Periods
+/// are rounded to the nearest tick, and lengthened to the shortest period the timing chain
+/// allows.
+pub fn frame_periods(durations: &[Duration], tick_rate: f64) -> Vec<u32> {
+    durations
+        .iter()
+        .map(|duration| {
+            (duration.as_secs_f64() * tick_rate)
+                .round()
+                .max(2.0 * MIN_HALF_PERIOD as f64) as u32
+        })
+        .collect()
+}
+
+/// Moves each chunking delayer from its place every `chunk_interval` frames to the nearest
+/// scene cut less than half an interval away, so that its extra tick of latency falls between
+/// scenes rather than in the middle of motion. Chunks with no cut nearby stay where they are.
+///
+/// `cuts` are the frames that start a new scene, in increasing order.
+pub fn snap_chunks_to_cuts(frames: usize, chunk_interval: usize, cuts: &[usize]) -> Vec<usize> {
+    let reach = chunk_interval / 2;
+    (chunk_interval - 1..frames)
+        .step_by(chunk_interval)
+        .map(|chunk| {
+            // Neighbouring chunks search disjoint ranges, so they never land on the same cut.
+            cuts.iter()
+                .copied()
+                .filter(|&cut| cut + reach > chunk && cut < chunk + reach)
+                .min_by_key(|&cut| cut.abs_diff(chunk))
+                .unwrap_or(chunk)
+        })
+        .collect()
+}
+
+/// Builds the circuitry that triggers each timing step, independently of how pixels are encoded.
+pub trait TimingGenerator {
+    /// Adds the timing circuit for every row, returning for each row the components whose inputs
+    /// carry the pulse of each timing step.
+    fn build(&self, circuit: &mut Circuit, rows: usize) -> Vec<Vec<NodeId>>;
+
+    /// Tick at which the pulse of each timing step arrives, relative to the start pulse.
+    fn arrivals(&self) -> Vec<i64>;
+}
+
+/// Drives timing steps from a chain of delayers, one per step, in every row.
+pub struct DelayerChain {
+    delays: Vec<u32>,
+}
+
+impl DelayerChain {
+    pub fn new(timing: &Timing, depth: usize) -> Self {
+        Self {
+            delays: (0..depth).map(|z| timing.delayer_delay(z)).collect(),
+        }
+    }
+}
+
+impl TimingGenerator for DelayerChain {
+    fn build(&self, circuit: &mut Circuit, rows: usize) -> Vec<Vec<NodeId>> {
+        (0..rows)
+            .map(|row| {
+                let delayers: Vec<NodeId> = self
+                    .delays
+                    .iter()
+                    .enumerate()
+                    .map(|(z, &delay)| {
+                        circuit.add(Kind::Delayer { delay }, Slot::Timing { row, z })
+                    })
+                    .collect();
+                for pair in delayers.windows(2) {
+                    circuit.wire(Pin::Output(pair[0]), Pin::Input(pair[1]));
+                }
+                delayers
+            })
+            .collect()
+    }
+
+    fn arrivals(&self) -> Vec<i64> {
+        let mut arrival = 0;
+        self.delays
+            .iter()
+            .map(|&delay| {
+                let this = arrival;
+                arrival += i64::from(delay);
+                this
+            })
+            .collect()
+    }
+}
+
+/// A frame that does not reach the display exactly one period after the previous one.
+pub struct TimingViolation {
+    pub frame: usize,
+    /// Ticks between the previous frame and this one.
+    pub interval: i64,
+}
+
+/// Computes when each frame's pixel updates reach the display, relative to the start pulse,
+/// and reports every frame that is not displayed one period of its predecessor after it.
+///
+/// `arrivals` are the timing step arrival ticks reported by the [`TimingGenerator`], and
+/// `chunk_frames` are the frames at which chunking delayers were inserted into the pixel columns.
+pub fn check_timing(
+    arrivals: &[i64],
+    chunk_frames: &[usize],
+    frames: usize,
+    timing: &Timing,
+) -> Vec<TimingViolation> {
+    // Frame `f` is driven from timing step `2 * (f + 1)`, and its pixel updates
+    // pass through every chunking delayer inserted at or before it on the way to the display.
+    let display_ticks: Vec<i64> = (0..frames)
+        .map(|frame| {
+            let chunks = chunk_frames.iter().filter(|&&c| c <= frame).count() as i64;
+            arrivals[(frame + 1) * 2]
+                + i64::from(timing.pixel_delay())
+                + chunks * i64::from(CHUNK_DELAY)
+        })
+        .collect();
+
+    display_ticks
+        .windows(2)
+        .enumerate()
+        .map(|(i, pair)| TimingViolation {
+            frame: i + 1,
+            interval: pair[1] - pair[0],
+        })
+        .filter(|violation| violation.interval != i64::from(timing.period_of(violation.frame - 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(timing: &Timing, frames: usize, chunk_frames: &[usize]) -> Vec<(usize, i64)> {
+        let chain = DelayerChain::new(timing, frames * 2 + 1);
+        check_timing(&chain.arrivals(), chunk_frames, frames, timing)
+            .into_iter()
+            .map(|violation| (violation.frame, violation.interval))
+            .collect()
+    }
+
+    #[test]
+    fn chunk_compensation_keeps_frames_on_time() {
+        let timing = Timing {
+            guard_ticks: 2,
+            start_delay: 30,
+            ..Timing::default()
+        };
+        let frames = 2 * CHUNK_INTERVAL + 10;
+        let chunk_frames = [CHUNK_INTERVAL - 1, 2 * CHUNK_INTERVAL - 1];
+        assert!(violations(&timing, frames, &chunk_frames).is_empty());
+        // Without the chunking delayers, the compensated frames arrive a tick early.
+        let early = i64::from(timing.frame_period() - CHUNK_DELAY);
+        assert_eq!(
+            violations(&timing, frames, &[]),
+            [(chunk_frames[0], early), (chunk_frames[1], early)]
+        );
+    }
+
+    #[test]
+    fn chunks_snap_to_nearby_scene_cuts() {
+        let frames = 3 * CHUNK_INTERVAL + 10;
+        let nominal = [
+            CHUNK_INTERVAL - 1,
+            2 * CHUNK_INTERVAL - 1,
+            3 * CHUNK_INTERVAL - 1,
+        ];
+        assert_eq!(snap_chunks_to_cuts(frames, CHUNK_INTERVAL, &[]), nominal);
+        // The first cut is too early for any chunk, the nearer of the next two wins, and the last
+        // lies exactly between the second and third chunks, out of reach of both.
+        let cuts = [
+            10,
+            nominal[0] - 20,
+            nominal[0] + 5,
+            nominal[1] + CHUNK_INTERVAL / 2,
+        ];
+        assert_eq!(
+            snap_chunks_to_cuts(frames, CHUNK_INTERVAL, &cuts),
+            [nominal[0] + 5, nominal[1], nominal[2]]
+        );
+    }
+
+    #[test]
+    fn compensation_follows_the_chunk_schedule() {
+        let chunk_frames = vec![7, CHUNK_INTERVAL + 40];
+        let timing = Timing {
+            chunk_frames: Some(chunk_frames.clone()),
+            ..Timing::default()
+        };
+        let frames = 2 * CHUNK_INTERVAL;
+        assert!(violations(&timing, frames, &chunk_frames).is_empty());
+        assert!(!violations(&timing, frames, &[CHUNK_INTERVAL - 1]).is_empty());
+    }
+
+    #[test]
+    fn frames_follow_their_own_periods() {
+        let timing = Timing {
+            chunk_interval: 2,
+            frame_periods: vec![20, 35, 8, 20, 41],
+            ..Timing::default()
+        };
+        assert!(violations(&timing, 6, &[1, 3, 5]).is_empty());
+    }
+}
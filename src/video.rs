@@ -0,0 +1,260 @@
+//! Decoding of frames straight from a video file, through the `ffmpeg` and `ffprobe` commands.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail, Context};
+use image::{DynamicImage, GrayImage};
+use logicworld_badapple::source::FrameSource;
+
+/// Number of frames before the one last requested that are kept for other threads.
+const LOOKBEHIND: usize = 64;
+
+/// The frames of a video file, decoded one at a time as they are requested.
+///
+/// The video is decoded by an `ffmpeg` process into raw grayscale frames. Frames decoded ahead of
+/// the one requested are kept until they fall [`LOOKBEHIND`] frames behind, so that frames
+/// requested again, such as those repeated when resampling, are not decoded twice. Requesting a
+/// frame older than that restarts decoding from the beginning.
+pub struct VideoFile {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    len: usize,
+    stream: Mutex<Stream>,
+}
+
+/// A running `ffmpeg` process, killed when dropped.
+struct Decoder {
+    child: Option<Child>,
+    reader: Box<dyn Read + Send>,
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    decoder: Option<Decoder>,
+    /// Index of the next frame the decoder produces.
+    next: usize,
+    /// Frames already decoded, by index.
+    decoded: BTreeMap<usize, DynamicImage>,
+}
+
+impl Stream {
+    /// Returns the frame at `index`, decoding up to it with a decoder from `start` if the
+    /// running one is missing or already past it.
+    fn frame(
+        &mut self,
+        index: usize,
+        (width, height): (u32, u32),
+        start: impl FnOnce() -> anyhow::Result<Decoder>,
+    ) -> anyhow::Result<DynamicImage> {
+        if let Some(frame) = self.decoded.get(&index) {
+            return Ok(frame.clone());
+        }
+        if index < self.next || self.decoder.is_none() {
+            *self = Stream {
+                decoder: Some(start()?),
+                ..Stream::default()
+            };
+        }
+
+        let frame_size = width as usize * height as usize;
+        loop {
+            let mut pixels = vec![0; frame_size];
+            let decoder = self.decoder.as_mut().expect("decoder was started");
+            decoder.reader.read_exact(&mut pixels)?;
+            let frame = DynamicImage::ImageLuma8(
+                GrayImage::from_raw(width, height, pixels).expect("buffer fits frame"),
+            );
+            let decoded = self.next;
+            self.next += 1;
+            if decoded == index {
+                self.decoded.insert(decoded, frame.clone());
+                // Frames far behind are never going to be requested, such as those skipped when
+                // decimating, and would otherwise pile up.
+                let kept = self.decoded.split_off(&index.saturating_sub(LOOKBEHIND));
+                self.decoded = kept;
+                return Ok(frame);
+            }
+            self.decoded.insert(decoded, frame);
+        }
+    }
+}
+
+/// Probes the average frame rate of the first video stream of the file at `path`.
+pub fn frame_rate(path: &Path) -> anyhow::Result<f64> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=avg_frame_rate"])
+        .args(["-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .context("cannot run ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "cannot probe {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // The rate is given as a fraction, such as 30000/1001.
+    let rate = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let fps = match rate.split_once('/') {
+        Some((numerator, denominator)) => numerator.parse::<f64>()? / denominator.parse::<f64>()?,
+        None => rate.parse()?,
+    };
+    if !(fps.is_finite() && fps > 0.0) {
+        bail!("{:?} does not report a frame rate", path);
+    }
+    Ok(fps)
+}
+
+/// Probes the title in the container metadata of the file at `path`, if it has one.
+pub fn title(path: &Path) -> anyhow::Result<Option<String>> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error"])
+        .args(["-show_entries", "format_tags=title"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .context("cannot run ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "cannot probe {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let title = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Ok((!title.is_empty()).then_some(title))
+}
+
+impl VideoFile {
+    /// Probes the video at `path` for its size and frame count.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0", "-count_packets"])
+            .args(["-show_entries", "stream=width,height,nb_read_packets"])
+            .args(["-of", "csv=p=0"])
+            .arg(path)
+            .output()
+            .context("cannot run ffprobe")?;
+        if !output.status.success() {
+            bail!(
+                "cannot probe {:?}: {}",
+                path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let fields: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .split(',')
+            .map(str::to_owned)
+            .collect();
+        let [width, height, len] = &fields[..] else {
+            bail!("{:?} has no video stream", path);
+        };
+        let video = Self {
+            path: path.to_owned(),
+            width: width.parse()?,
+            height: height.parse()?,
+            len: len
+                .parse()
+                .with_context(|| format!("cannot count the frames of {:?}", path))?,
+            stream: Mutex::default(),
+        };
+        if video.len == 0 {
+            bail!("no frames found in {:?}", path);
+        }
+        Ok(video)
+    }
+
+    fn spawn(&self) -> anyhow::Result<Decoder> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-v", "error", "-i"])
+            .arg(&self.path)
+            .args(["-map", "0:v:0", "-f", "rawvideo", "-pix_fmt", "gray", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("cannot run ffmpeg")?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Decoder {
+            child: Some(child),
+            reader: Box::new(BufReader::new(stdout)),
+        })
+    }
+}
+
+impl FrameSource for VideoFile {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        let mut stream = self
+            .stream
+            .lock()
+            .map_err(|_| anyhow!("video decoder panicked"))?;
+        stream
+            .frame(index, (self.width, self.height), || self.spawn())
+            .with_context(|| format!("cannot decode frame {} of {:?}", index, self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Requests `indices` from a stream of 1x1 frames whose pixel holds their index, returning
+    /// the pixels and how many times decoding started.
+    fn request(indices: &[usize]) -> (Vec<u8>, usize) {
+        let mut stream = Stream::default();
+        let mut starts = 0;
+        let pixels = indices
+            .iter()
+            .map(|&index| {
+                let frame = stream
+                    .frame(index, (1, 1), || {
+                        starts += 1;
+                        Ok(Decoder {
+                            child: None,
+                            reader: Box::new(Cursor::new((0..=255).collect::<Vec<u8>>())),
+                        })
+                    })
+                    .unwrap();
+                frame.to_luma8().get_pixel(0, 0).0[0]
+            })
+            .collect();
+        (pixels, starts)
+    }
+
+    #[test]
+    fn repeated_frames_are_not_decoded_again() {
+        let indices = [0, 0, 0, 1, 1, 2, 2, 2];
+        assert_eq!(request(&indices), (vec![0, 0, 0, 1, 1, 2, 2, 2], 1));
+        assert_eq!(request(&[3, 1, 2, 0]), (vec![3, 1, 2, 0], 1));
+    }
+
+    #[test]
+    fn frames_far_behind_restart_decoding() {
+        let far = LOOKBEHIND + 10;
+        assert_eq!(request(&[far, 5]), (vec![far as u8, 5], 2));
+    }
+}
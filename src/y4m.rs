@@ -0,0 +1,240 @@
+//! Reading of frames from yuv4mpeg2 streams, as written by `ffmpeg -f yuv4mpeg2`.
+//!
+//! Only the luma plane of each frame is used, which is all the preprocessing needs.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use image::{DynamicImage, GrayImage};
+use logicworld_badapple::source::FrameSource;
+
+/// Frames of a yuv4mpeg2 stream.
+///
+/// Frames of a file are read straight from disk when they are requested, so only their offsets
+/// are kept in memory. A stream read from standard input cannot be revisited, so the luma plane
+/// of each of its frames is kept instead.
+pub struct Y4m {
+    width: u32,
+    height: u32,
+    frames: Frames,
+}
+
+enum Frames {
+    File { path: PathBuf, offsets: Vec<u64> },
+    Memory(Vec<GrayImage>),
+}
+
+/// Frame size and the number of bytes in a frame after its luma plane.
+struct Header {
+    width: u32,
+    height: u32,
+    chroma_size: u64,
+}
+
+impl Header {
+    fn luma_size(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+fn read_header(reader: &mut impl BufRead) -> anyhow::Result<Header> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut fields = line.trim_end().split(' ');
+    if fields.next() != Some("YUV4MPEG2") {
+        bail!("not a yuv4mpeg2 stream");
+    }
+    let (mut width, mut height) = (None, None);
+    let mut colorspace = "420jpeg";
+    for field in fields {
+        let mut chars = field.chars();
+        let tag = chars.next();
+        let value = chars.as_str();
+        match tag {
+            Some('W') => width = Some(value.parse::<u32>()?),
+            Some('H') => height = Some(value.parse::<u32>()?),
+            Some('C') => colorspace = value,
+            _ => {}
+        }
+    }
+    let (Some(width), Some(height)) = (width, height) else {
+        bail!("yuv4mpeg2 header lacks the frame size");
+    };
+
+    let (chroma_width, chroma_height) = (u64::from(width.div_ceil(2)), u64::from(height));
+    let chroma_size = match colorspace {
+        "mono" => 0,
+        "444" => 2 * u64::from(width) * chroma_height,
+        "422" => 2 * chroma_width * chroma_height,
+        "411" => 2 * u64::from(width.div_ceil(4)) * chroma_height,
+        "420" | "420jpeg" | "420mpeg2" | "420paldv" => {
+            2 * chroma_width * u64::from(height.div_ceil(2))
+        }
+        _ => bail!("unsupported yuv4mpeg2 colorspace {:?}", colorspace),
+    };
+    Ok(Header {
+        width,
+        height,
+        chroma_size,
+    })
+}
+
+/// Reads the header line of the next frame, returning its length in bytes, or `None` at the end
+/// of the stream.
+fn read_frame_header(reader: &mut impl BufRead) -> anyhow::Result<Option<u64>> {
+    let mut line = Vec::new();
+    let length = reader.read_until(b'\n', &mut line)?;
+    if length == 0 {
+        return Ok(None);
+    }
+    if !line.starts_with(b"FRAME") {
+        bail!("malformed yuv4mpeg2 frame header");
+    }
+    Ok(Some(length as u64))
+}
+
+impl Y4m {
+    /// Indexes the frames of the stream at `path`, or reads them from standard input if `path`
+    /// is `-`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let y4m = if path == Path::new("-") {
+            Self::read_stdin()?
+        } else {
+            Self::index_file(path)?
+        };
+        if y4m.is_empty() {
+            bail!("no frames found in {:?}", path);
+        }
+        Ok(y4m)
+    }
+
+    fn index_file(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let header = read_header(&mut reader)?;
+        let frame_size = header.luma_size() + header.chroma_size;
+
+        let mut offsets = Vec::new();
+        let mut offset = reader.stream_position()?;
+        while let Some(length) = read_frame_header(&mut reader)? {
+            offset += length;
+            if offset + frame_size > file_size {
+                bail!("frame {} of {:?} is truncated", offsets.len(), path);
+            }
+            offsets.push(offset);
+            offset += frame_size;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(Self {
+            width: header.width,
+            height: header.height,
+            frames: Frames::File {
+                path: path.to_owned(),
+                offsets,
+            },
+        })
+    }
+
+    fn read_stdin() -> anyhow::Result<Self> {
+        let mut reader = io::stdin().lock();
+        let header = read_header(&mut reader)?;
+        let mut frames = Vec::new();
+        while read_frame_header(&mut reader)?.is_some() {
+            let mut luma = vec![0; header.luma_size() as usize];
+            reader
+                .read_exact(&mut luma)
+                .with_context(|| format!("frame {} is truncated", frames.len()))?;
+            io::copy(&mut (&mut reader).take(header.chroma_size), &mut io::sink())?;
+            frames.push(
+                GrayImage::from_raw(header.width, header.height, luma).expect("buffer fits frame"),
+            );
+        }
+        Ok(Self {
+            width: header.width,
+            height: header.height,
+            frames: Frames::Memory(frames),
+        })
+    }
+}
+
+impl FrameSource for Y4m {
+    fn len(&self) -> usize {
+        match &self.frames {
+            Frames::File { offsets, .. } => offsets.len(),
+            Frames::Memory(frames) => frames.len(),
+        }
+    }
+
+    fn frame(&self, index: usize) -> anyhow::Result<DynamicImage> {
+        let luma = match &self.frames {
+            Frames::File { path, offsets } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offsets[index]))?;
+                let mut luma = vec![0; self.width as usize * self.height as usize];
+                file.read_exact(&mut luma)?;
+                GrayImage::from_raw(self.width, self.height, luma).expect("buffer fits frame")
+            }
+            Frames::Memory(frames) => frames[index].clone(),
+        };
+        Ok(DynamicImage::ImageLuma8(luma))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Cursor, process};
+
+    use super::*;
+
+    fn header(line: &str) -> anyhow::Result<Header> {
+        read_header(&mut Cursor::new(line))
+    }
+
+    #[test]
+    fn chroma_planes_follow_the_colorspace() {
+        let chroma_size = |colorspace: &str| {
+            header(&format!("YUV4MPEG2 W5 H3 F25:1 Ip A1:1{}\n", colorspace))
+                .unwrap()
+                .chroma_size
+        };
+        // Subsampled planes round up, so a 5x3 frame has 3x2 chroma planes in 4:2:0.
+        assert_eq!(chroma_size(""), 2 * 3 * 2);
+        assert_eq!(chroma_size(" C420mpeg2"), 2 * 3 * 2);
+        assert_eq!(chroma_size(" C422"), 2 * 3 * 3);
+        assert_eq!(chroma_size(" C411"), 2 * 2 * 3);
+        assert_eq!(chroma_size(" C444"), 2 * 5 * 3);
+        assert_eq!(chroma_size(" Cmono"), 0);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let parsed = header("YUV4MPEG2 W4 H2\n").unwrap();
+        assert_eq!((parsed.width, parsed.height, parsed.luma_size()), (4, 2, 8));
+        assert!(header("YUV4MPEG W4 H2\n").is_err());
+        assert!(header("YUV4MPEG2 W4\n").is_err());
+        assert!(header("YUV4MPEG2 W4 H2 C420p10\n").is_err());
+    }
+
+    #[test]
+    fn frames_are_read_from_their_offsets() {
+        let path = env::temp_dir().join(format!("y4m-test-{}.y4m", process::id()));
+        let mut stream = b"YUV4MPEG2 W2 H2 C420jpeg\n".to_vec();
+        for frame in 0..3u8 {
+            stream.extend(b"FRAME\n");
+            stream.extend([frame; 4]);
+            stream.extend([128; 2]);
+        }
+        fs::write(&path, &stream).unwrap();
+        let y4m = Y4m::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        let y4m = y4m.unwrap();
+        assert_eq!(y4m.len(), 3);
+        assert_eq!(y4m.frame(2).unwrap().to_luma8().into_raw(), [2; 4]);
+    }
+}